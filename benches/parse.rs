@@ -0,0 +1,80 @@
+//! 大きな文書のパース時間を計測する
+//!
+//! `cargo bench` で実行する。各ケースは 10,000 行前後の文書で、数ミリ秒程度で終わることを期待している。
+
+use std::time::{Duration, Instant};
+
+const LINE_COUNT: usize = 10_000;
+const ITERATIONS: u32 = 10;
+
+fn prose() -> String {
+    let mut src = String::from("%title bench\n\n");
+    for i in 0..LINE_COUNT {
+        if i % 5 == 4 {
+            src.push('\n');
+        } else {
+            src.push_str("文章 $x_i$ と `code` と [link](https://example.com) と %Small% の行\n");
+        }
+    }
+    src
+}
+
+fn indented_paragraph() -> String {
+    // 横線の続かない字下げ行の連続。推論規則かどうかの判定が行ごとにやり直されると遅くなる
+    let mut src = String::from("head\n");
+    for _ in 0..LINE_COUNT {
+        src.push_str("  $\\Gamma \\vdash e \\colon \\tau$\n");
+    }
+    src
+}
+
+fn derivations() -> String {
+    let mut src = String::new();
+    for _ in 0..LINE_COUNT / 8 {
+        src.push_str("    $A$\n  --- %R1%\n    $B$\n  $C$\n--------- %R2%\n  $D$\n\n");
+    }
+    src
+}
+
+fn nested_blocks() -> String {
+    let mut src = String::new();
+    for _ in 0..LINE_COUNT / 10 {
+        src.push_str("Thm. title\n  - item\n    > quote $x$\n  proof.\n    - a\n      - b\n```\ncode\n```\n$$\nx\n$$\n");
+    }
+    src
+}
+
+fn unclosed_delimiters() -> String {
+    // 閉じていない区切り文字だらけの行。文字ごとに閉じ記号を探し直すと行の長さの 2 乗かかる
+    let line = "$ ` % [ ] ( ".repeat(100);
+    let mut src = String::new();
+    for _ in 0..LINE_COUNT / 10 {
+        src.push_str(&line);
+        src.push_str("\n\n");
+    }
+    src
+}
+
+fn bench(name: &str, src: &str) {
+    let mut total = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        let parsed = bokuteki::parse(src);
+        total += start.elapsed();
+        std::hint::black_box(parsed);
+    }
+    println!(
+        "{:<24} {:>6} lines  {:>10.3?} / iter",
+        name,
+        src.lines().count(),
+        total / ITERATIONS
+    );
+}
+
+fn main() {
+    bench("prose", &prose());
+    bench("indented_paragraph", &indented_paragraph());
+    bench("derivations", &derivations());
+    bench("nested_blocks", &nested_blocks());
+    bench("unclosed_delimiters", &unclosed_delimiters());
+}
@@ -0,0 +1,20 @@
+// bokuteki が数式をまとめて描画するためのスクリプト
+// `npx --package katex -c "node render-batch.js input.json"` として実行する
+// input.json は [TeX, display mode か] の配列。数式ごとに {"ok": HTML} か {"err": メッセージ} を出力する
+const fs = require("fs");
+const path = require("path");
+
+// npx は katex を入れたディレクトリの node_modules/.bin を PATH に加える
+const katex = require(
+    require.resolve("katex", { paths: process.env.PATH.split(path.delimiter) })
+);
+
+const requests = JSON.parse(fs.readFileSync(process.argv[2], "utf8"));
+const results = requests.map(([src, displayMode]) => {
+    try {
+        return { ok: katex.renderToString(src, { displayMode }) };
+    } catch (err) {
+        return { err: String(err.message) };
+    }
+});
+process.stdout.write(JSON.stringify(results));
@@ -0,0 +1,69 @@
+// bokuteki serve が注入するライブリロードのクライアント
+(function () {
+    const STATE_KEY = "bokuteki-livereload";
+
+    function parse(html) {
+        return new DOMParser().parseFromString(html, "text/html");
+    }
+
+    // 2 つの文書の body 直下の要素を比べ、最初に異なるものの位置を返す
+    function firstChangedBlock(oldHtml, newHtml) {
+        const oldBlocks = parse(oldHtml).body.children;
+        const newBlocks = parse(newHtml).body.children;
+        for (let i = 0; i < newBlocks.length; i++) {
+            if (i >= oldBlocks.length || !oldBlocks[i].isEqualNode(newBlocks[i])) {
+                return i;
+            }
+        }
+        return oldBlocks.length === newBlocks.length ? null : newBlocks.length - 1;
+    }
+
+    function isVisible(element) {
+        const rect = element.getBoundingClientRect();
+        return rect.bottom > 0 && rect.top < window.innerHeight;
+    }
+
+    // リロード前の位置を復元し、変わったブロックが画面外ならそこへ移動する
+    window.addEventListener("load", function () {
+        const saved = sessionStorage.getItem(STATE_KEY);
+        if (saved === null) {
+            return;
+        }
+        sessionStorage.removeItem(STATE_KEY);
+        const state = JSON.parse(saved);
+        window.scrollTo(0, state.scrollY);
+        const block = state.changedBlock === null ? null : document.body.children[state.changedBlock];
+        if (block && !isVisible(block)) {
+            block.scrollIntoView({ block: "center" });
+        }
+    });
+
+    let current = fetch(location.href, { cache: "no-store" }).then(function (response) {
+        return response.text();
+    });
+
+    function reload(changedBlock) {
+        sessionStorage.setItem(STATE_KEY, JSON.stringify({
+            scrollY: window.scrollY,
+            changedBlock: changedBlock,
+        }));
+        location.reload();
+    }
+
+    const events = new EventSource("/__bokuteki/events");
+    events.addEventListener("reload", function (event) {
+        const message = JSON.parse(event.data);
+        Promise.all([
+            current,
+            fetch(location.href, { cache: "no-store" }).then(function (response) {
+                return response.text();
+            }),
+        ]).then(function ([oldHtml, newHtml]) {
+            if (message.full) {
+                reload(null);
+            } else if (oldHtml !== newHtml) {
+                reload(firstChangedBlock(oldHtml, newHtml));
+            }
+        });
+    });
+})();
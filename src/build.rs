@@ -1,54 +1,632 @@
-use crate::{document, parse, print::Printer};
-use std::collections::VecDeque;
+use crate::cache::{self, BuildManifest, FileEntry};
+use crate::diagnostic::Diagnostic;
+use crate::document::{Document, Import, Source};
+use crate::include::{self, Includer};
+use crate::lint::{self, PageLink};
+use crate::parse::{self, Error, Warning};
+use crate::print::{self, PageOptions, Printer, RenderOptions, Template};
+use crate::util;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::{Path, PathBuf};
-
-pub fn build(src_path: PathBuf, output_path: PathBuf) {
-    let printer = Printer::setup(output_path);
-
-    let mut q = VecDeque::new();
-    let root_path = src_path.parent().unwrap().to_path_buf();
-    q.push_back(PathBuf::from(src_path.file_name().unwrap()));
-    while let Some(import_path) = q.pop_front() {
-        if let Ok(content) = fs::read_to_string(root_path.join(&import_path)) {
-            let imports = compile(&printer, &import_path, content);
-            let import_base_path = import_path.parent().unwrap();
-            q.append(
-                &mut imports
-                    .into_iter()
-                    .map(|child| {
-                        let mut path = import_base_path.join(child);
-                        path.set_extension("bok");
-                        path
-                    })
-                    .collect(),
-            );
-        } else {
-            eprintln!("[error] input file not found: {}", import_path.display());
-        }
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// ビルドの設定
+#[derive(Debug, Clone)]
+pub struct BuildConfig {
+    /// 起点となる .bok ファイル。すべて `root` の下にあること
+    pub entries: Vec<PathBuf>,
+    /// プロジェクトの根。ファイルのパスはここからの相対パスで扱い、出力先にも同じ構造で出力する
+    pub root: PathBuf,
+    /// 出力先ディレクトリ
+    pub output_dir: PathBuf,
+    /// template.html, bokuteki.css, bokuteki.js を含むディレクトリ。None なら埋め込みのテンプレートを使う
+    pub template_dir: Option<PathBuf>,
+    /// import 先を探すディレクトリ (`root` からの相対パス)。import したファイルのディレクトリの次に、順に探す
+    pub search_paths: Vec<PathBuf>,
+    pub render_options: RenderOptions,
+    pub page_options: PageOptions,
+    /// パースエラーがあっても、失敗した箇所をエラー表示にして HTML を出力する
+    pub keep_going: bool,
+    /// 警告をエラーとして扱う
+    pub deny_warnings: bool,
+    /// bokuteki の出力ではない空でないディレクトリにも出力する
+    pub force: bool,
+}
+
+/// ビルド結果
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    pub files: Vec<FileReport>,
+    /// 読めなかった起点のファイル (プロジェクトルートからの相対パス)。
+    /// 見つからなかった import 先は、import したファイルの "missing-import" のエラーになる
+    pub missing_files: Vec<PathBuf>,
+    /// excerpt で抜き出したファイル (プロジェクトルートからの相対パス)
+    pub excerpt_files: Vec<PathBuf>,
+}
+
+impl BuildReport {
+    pub fn has_errors(&self) -> bool {
+        self.files.iter().any(|file| !file.errors.is_empty())
+    }
+
+    /// 見つからなかったファイルがあるか
+    pub fn has_missing_files(&self) -> bool {
+        !self.missing_files.is_empty() || self.has_missing_imports()
+    }
+
+    /// 見つからなかった import 先があるか
+    pub fn has_missing_imports(&self) -> bool {
+        self.files.iter().any(has_missing_import)
+    }
+}
+
+/// 1 ファイル分のビルド結果
+#[derive(Debug, Clone)]
+pub struct FileReport {
+    /// プロジェクトルートからの相対パス
+    pub path: PathBuf,
+    /// ファイルの中身。診断の range はこれを指す
+    pub content: String,
+    pub errors: Vec<Error>,
+    pub warnings: Vec<Warning>,
+}
+
+/// ビルドを繰り返すときに、前回から中身の変わっていないファイルの結果を使い回すためのもの
+///
+/// テンプレートやビルドの設定が変わったときは `clear` してすべて作り直す。
+#[derive(Debug, Default)]
+pub struct BuildCache {
+    // 根からの相対パス -> (import 先, 結果)。`FileReport::content` で変更を判定する
+    // import 先が見つからなかったファイルは、新しく作られたかもしれないので使い回さない
+    // include や excerpt をしているファイルは、取り込んだファイルの変更を判定できないので使い回さない
+    // include や excerpt に失敗したファイルも、取り込むはずのファイルが直されたかもしれないので使い回さない
+    files: HashMap<PathBuf, CachedFile>,
+}
+
+#[derive(Debug)]
+struct CachedFile {
+    imports: Vec<PathBuf>,
+    file_report: FileReport,
+    // ページ間のリンクの検査は毎回やり直すので、そのための情報も残す
+    links: Vec<PageLink>,
+    anchors: HashSet<String>,
+}
+
+impl BuildCache {
+    pub fn clear(&mut self) {
+        self.files.clear();
     }
 }
 
-fn compile(printer: &Printer, src_path: &Path, content: String) -> Vec<PathBuf> {
-    let content: Vec<Vec<char>> = content.lines().map(|line| line.chars().collect()).collect();
-    let src_block_range = document::src_block_range(&content);
+pub fn build(config: &BuildConfig) -> io::Result<BuildReport> {
+    build_with_cache(config, &mut BuildCache::default())
+}
+
+/// `cache` にある結果と中身の同じファイルは作り直さずにビルドする。`cache` は今回の結果に置き換わる
+///
+/// 出力ディレクトリに前回のビルドの記録があれば、入力の変わっていないページは
+/// パースと検査だけを行い、出力し直さない。
+///
+/// ファイルのパースと出力は並行に行う。katex で描画する数式はすべてのページから集めて、
+/// 重複を除いてからまとめて描画する。診断の順序は import を幅優先で辿った順で、実行ごとに変わらない。
+pub fn build_with_cache(config: &BuildConfig, cache: &mut BuildCache) -> io::Result<BuildReport> {
+    let template = match &config.template_dir {
+        Some(template_dir) => Template::load(template_dir)?,
+        None => Template::embedded(),
+    };
+    let previous_manifest = BuildManifest::load(&config.output_dir);
+    let mut manifest = BuildManifest::new(config_hash(config, &template));
+    let printer = Printer::setup(
+        config.output_dir.clone(),
+        template,
+        config.render_options.clone(),
+        config.page_options.clone(),
+        config.force,
+    )?;
+
+    let walked = walk_imports(
+        &config.root,
+        &config.entries,
+        |root_path, src_path, content| {
+            if let Some(cached) = cache.files.get(src_path) {
+                let file_report = &cached.file_report;
+                if file_report.content == content && !has_missing_import(file_report) {
+                    return Ok((cached.imports.clone(), (file_report.clone(), None)));
+                }
+            }
+            let (imports, mut file_report, document) =
+                analyze(root_path, src_path, content, &config.search_paths);
+            if config.deny_warnings {
+                file_report.errors.append(&mut file_report.warnings);
+            }
+            Ok((imports, (file_report, Some(document))))
+        },
+    )?;
+
+    // 出力し直すページを決める
+    let mut report = BuildReport {
+        missing_files: walked.missing_files,
+        ..BuildReport::default()
+    };
+    let mut files = HashMap::new();
+    let mut to_print = vec![];
+    // 出力し終えてから記録する、出力するページの入力
+    let mut entries = vec![];
+    let mut includer = Includer::new(&config.root, &config.search_paths, config.deny_warnings);
+    let mut links = HashMap::new();
+    let mut pages = HashMap::new();
+    for (imports, (mut file_report, mut document)) in walked.files {
+        let src_path = file_report.path.clone();
+        let (dependencies, page_links, anchors) = match &mut document {
+            Some(document) => {
+                includer.expand(&mut file_report, &mut document.block_elements);
+                let src = Source::new(&file_report.content);
+                (
+                    include::dependencies(&document.block_elements),
+                    lint::page_links(&src, &src_path, &document.block_elements),
+                    lint::anchors(&src, &document.block_elements),
+                )
+            }
+            // 使い回す結果は、パースしたときのものを使う
+            None => {
+                let cached = &cache.files[&src_path];
+                (vec![], cached.links.clone(), cached.anchors.clone())
+            }
+        };
+        let dependencies: Vec<_> = dependencies
+            .iter()
+            .map(|(path, content)| (path.as_path(), content.as_str()))
+            .collect();
+        let entry = FileEntry::new(&file_report.content, &dependencies);
+        let fresh =
+            previous_manifest.is_fresh(&manifest, &src_path, &entry) && printer.has_page(&src_path);
+        match document {
+            Some(document) if !fresh && (can_print(&file_report) || config.keep_going) => {
+                // エラーのまま出力したページは、原因がソースの外で直ったときに出力し直せるよう記録しない
+                if can_print(&file_report) {
+                    entries.push((src_path.clone(), entry));
+                }
+                to_print.push((file_report.clone(), document));
+            }
+            // 前回の出力がそのまま使えるか、エラーで出力しない場合は前回のものを残す
+            _ => {
+                manifest.carry_over(&previous_manifest, &src_path);
+                printer.keep(&src_path);
+            }
+        }
+        report.files.push(file_report.clone());
+        if dependencies.is_empty() && !include::has_expand_error(&file_report) {
+            let cached = CachedFile {
+                imports,
+                file_report,
+                links: page_links.clone(),
+                anchors: anchors.clone(),
+            };
+            files.insert(src_path.clone(), cached);
+        }
+        links.insert(src_path.clone(), page_links);
+        pages.insert(src_path, anchors);
+    }
+    finish_includes(&mut report, includer, &mut links);
+    check_page_links(&mut report, &links, &pages);
 
-    let result = parse::parse_document(&content, src_block_range);
-    if result.errors.is_empty() {
-        let title = if let Some(title) = result.value.preamble.get("title") {
+    let math_requests = util::parallel_map(&to_print, |(file_report, document)| {
+        printer.math_requests(&Source::new(&file_report.content), &document.block_elements)
+    });
+    let rendered_math = print::render_math_batch(math_requests.into_iter().flatten().collect());
+    util::parallel_map(&to_print, |(file_report, document)| {
+        let title = if let Some(title) = document.preamble.get("title") {
             title
         } else {
             "Document"
         };
-        printer.print(&content, src_path, result.value.block_elements, title);
+        printer.print(
+            &Source::new(&file_report.content),
+            &file_report.path,
+            &document.block_elements,
+            title,
+            &rendered_math,
+        )
+    })
+    .into_iter()
+    .collect::<io::Result<()>>()?;
+    for (src_path, entry) in entries {
+        manifest.insert(&src_path, entry);
     }
 
-    for error in &result.errors {
-        eprintln!("[error] {}: {}", src_path.display(), error);
+    printer.write_file(Path::new(cache::CACHE_FILE_NAME), manifest.to_json())?;
+    printer.finish()?;
+
+    cache.files = files;
+    Ok(report)
+}
+
+/// 1 つの文書を、import を辿らずに HTML にする。ファイルへの出力は一切行わない
+///
+/// `path` は診断に表示するパスで、相対リンクの検査と include の基準にもなる。
+/// include したファイルの中の診断は返さない。`.bok` へのリンクは `.html` に書き換えるが、
+/// ほかのページを読まないので、リンク先がビルドに含まれるかは検査しない。
+/// `page` が Some ならテンプレートに埋め込んだページ全体を、None なら本文の HTML だけを返す。
+/// パースエラーがあっても、失敗した箇所をエラー表示にして HTML を返す。
+pub fn render(
+    content: &str,
+    path: &Path,
+    options: &RenderOptions,
+    page: Option<(&Template, &PageOptions)>,
+) -> (String, FileReport) {
+    let src = Source::new(content);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let src_path = Path::new(path.file_name().unwrap_or_default());
+    let (mut file_report, mut document) = analyze_source(&src, dir, src_path);
+    Includer::new(dir, &[], false).expand(&mut file_report, &mut document.block_elements);
+    let body = print::render_html(&src, &document.block_elements, options);
+    let html = match page {
+        Some((template, page_options)) => {
+            let title = document
+                .preamble
+                .get("title")
+                .map_or("Document", String::as_str);
+            template.render_page(src_path, title, &body, page_options)
+        }
+        None => body,
+    };
+    (
+        html,
+        FileReport {
+            path: path.to_path_buf(),
+            ..file_report
+        },
+    )
+}
+
+/// `entries` から import を辿ってすべてのファイルを検査する。出力は一切行わない
+///
+/// `search_paths` は `BuildConfig::search_paths` と同じく、import 先を探すディレクトリ。
+pub fn check(
+    entries: &[PathBuf],
+    root: &Path,
+    search_paths: &[PathBuf],
+    deny_warnings: bool,
+) -> io::Result<BuildReport> {
+    let walked = walk_imports(root, entries, |root_path, src_path, content| {
+        let (imports, mut file_report, document) =
+            analyze(root_path, src_path, content, search_paths);
+        if deny_warnings {
+            file_report.errors.append(&mut file_report.warnings);
+        }
+        Ok((imports, (file_report, document)))
+    })?;
+    let mut includer = Includer::new(root, search_paths, deny_warnings);
+    let mut report = BuildReport {
+        missing_files: walked.missing_files,
+        ..BuildReport::default()
+    };
+    let mut links = HashMap::new();
+    let mut pages = HashMap::new();
+    for (_, (mut file_report, mut document)) in walked.files {
+        includer.expand(&mut file_report, &mut document.block_elements);
+        let src = Source::new(&file_report.content);
+        let src_path = file_report.path.clone();
+        links.insert(
+            src_path.clone(),
+            lint::page_links(&src, &src_path, &document.block_elements),
+        );
+        pages.insert(src_path, lint::anchors(&src, &document.block_elements));
+        report.files.push(file_report);
     }
-    for warning in &result.warnings {
-        eprintln!("[warning] {}: {}", src_path.display(), warning);
+    finish_includes(&mut report, includer, &mut links);
+    check_page_links(&mut report, &links, &pages);
+    Ok(report)
+}
+
+// include されただけのファイルの診断をページの診断のあとに並べ、抜き出したファイルを記録する
+// include されたファイルに書かれたリンクは `links` に加える
+fn finish_includes(
+    report: &mut BuildReport,
+    includer: Includer,
+    links: &mut HashMap<PathBuf, Vec<PageLink>>,
+) {
+    let expanded = includer.finish();
+    let pages: HashSet<_> = report.files.iter().map(|file| file.path.clone()).collect();
+    report.files.extend(
+        expanded
+            .reports
+            .into_iter()
+            .filter(|file| !pages.contains(&file.path)),
+    );
+    report.excerpt_files = expanded.excerpt_files;
+    for (path, included_links) in expanded.links {
+        links.entry(path).or_insert(included_links);
     }
+}
+
+// `.bok` へのリンクの先がビルドに含まれるページで、アンカーがそのページにあるかを検査する
+// `pages` はページの根からの相対パス -> ページ中の id
+// リンクの誤りはページの出力を止めない
+fn check_page_links(
+    report: &mut BuildReport,
+    links: &HashMap<PathBuf, Vec<PageLink>>,
+    pages: &HashMap<PathBuf, HashSet<String>>,
+) {
+    for file_report in &mut report.files {
+        if let Some(links) = links.get(&file_report.path) {
+            let mut errors = lint::check_page_links(links, pages);
+            file_report.errors.append(&mut errors);
+        }
+    }
+}
+
+// import を辿った結果。`files` は (import 先, 各ファイルの結果) を辿った順に並べたもの
+struct Walked<T> {
+    files: Vec<(Vec<PathBuf>, T)>,
+    missing_files: Vec<PathBuf>,
+}
+
+// `root` を根として `entries` から import を幅優先で辿り、各ファイルに `f` を適用する
+// `f` は (根のパス, 根からの相対パス, ファイルの中身) を受け取り、根からの相対パスにした import 先と結果を返す
+// 同じ深さのファイルは並行に処理する
+fn walk_imports<T: Send>(
+    root_path: &Path,
+    entries: &[PathBuf],
+    f: impl Fn(&Path, &Path, &str) -> io::Result<(Vec<PathBuf>, T)> + Sync,
+) -> io::Result<Walked<T>> {
+    let mut results = vec![];
+    let mut missing_files = vec![];
+
+    let mut visited = HashSet::new();
+    let mut frontier = vec![];
+    for entry in entries {
+        let relative = entry
+            .strip_prefix(root_path)
+            .ok()
+            .filter(|relative| relative.file_name().is_some())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "invalid entry file: {} (must be a file under {})",
+                        entry.display(),
+                        root_path.display()
+                    ),
+                )
+            })?;
+        // 同じファイルが複数回指定されても 1 度だけ処理する
+        if visited.insert(relative.to_path_buf()) {
+            frontier.push(relative.to_path_buf());
+        }
+    }
+    while !frontier.is_empty() {
+        let processed = util::parallel_map(&frontier, |import_path| {
+            match fs::read_to_string(root_path.join(import_path)) {
+                Ok(content) => f(root_path, import_path, &content).map(Some),
+                Err(_) => Ok(None),
+            }
+        });
+        let mut next = vec![];
+        for (import_path, result) in frontier.into_iter().zip(processed) {
+            match result? {
+                Some((imports, value)) => {
+                    // 循環した import や、複数のファイルからの import は 1 度だけ処理する
+                    next.extend(
+                        imports
+                            .iter()
+                            .filter(|path| visited.insert(path.to_path_buf()))
+                            .cloned(),
+                    );
+                    results.push((imports, value));
+                }
+                None => missing_files.push(import_path),
+            }
+        }
+        frontier = next;
+    }
+    Ok(Walked {
+        files: results,
+        missing_files,
+    })
+}
+
+/// 起点のファイルの指定に含まれる glob のパターンを展開する
+///
+/// パターンを含まない指定はそのまま返す。パターンに一致するファイルがなければエラーを返す。
+pub fn expand_entries(patterns: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut entries: Vec<PathBuf> = vec![];
+    for pattern in patterns {
+        let pattern_str = pattern.to_string_lossy();
+        if !pattern_str.contains(['*', '?', '[']) {
+            entries.push(pattern.clone());
+            continue;
+        }
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let matches = glob::glob(&pattern_str)
+            .map_err(|err| invalid(format!("invalid pattern `{}`: {}", pattern_str, err)))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| io::Error::new(err.error().kind(), err.to_string()))?;
+        if matches.is_empty() {
+            return Err(invalid(format!("no files match `{}`", pattern_str)));
+        }
+        entries.extend(matches);
+    }
+    let mut seen = HashSet::new();
+    entries.retain(|entry| seen.insert(entry.clone()));
+    Ok(entries)
+}
+
+/// 起点のファイルすべてを含む最も深いディレクトリ
+pub fn common_root(entries: &[PathBuf]) -> PathBuf {
+    let mut dirs = entries
+        .iter()
+        .map(|entry| entry.parent().unwrap_or(Path::new("")));
+    let Some(first) = dirs.next() else {
+        return PathBuf::new();
+    };
+    dirs.fold(first.to_path_buf(), |root, dir| {
+        root.components()
+            .zip(dir.components())
+            .take_while(|(a, b)| a == b)
+            .map(|(a, _)| a)
+            .collect()
+    })
+}
+
+const MISSING_IMPORT: &str = "missing-import";
+
+// `src_path` の import 先を探し、根からの相対パスを返す。見つからなかった import はエラーにする
+fn resolve_imports(
+    root_path: &Path,
+    src_path: &Path,
+    imports: &[Import],
+    search_paths: &[PathBuf],
+) -> (Vec<PathBuf>, Vec<Error>) {
+    let mut resolved = vec![];
+    let mut errors = vec![];
+    for import in imports {
+        match find_file(root_path, src_path, &import.path, search_paths) {
+            Ok(path) => resolved.push(path),
+            Err(notes) => errors.push(notes.into_iter().fold(
+                Diagnostic::new(
+                    MISSING_IMPORT,
+                    import.range,
+                    format!("imported file '{}' is not found.", import.path.display()),
+                ),
+                Diagnostic::with_note,
+            )),
+        }
+    }
+    (resolved, errors)
+}
+
+/// `src_path` に書かれた `path` が指すファイルを探し、根からの相対パスを返す
+///
+/// `src_path` のディレクトリ、`search_paths` の順に探す。'..' で根の外に出る候補は使わない。
+/// 見つからなければ、試した候補を説明する診断の補足を返す。
+pub(crate) fn find_file(
+    root_path: &Path,
+    src_path: &Path,
+    path: &Path,
+    search_paths: &[PathBuf],
+) -> Result<PathBuf, Vec<String>> {
+    // 'a.b' のようにドットを含む名前もあるので、拡張子は置き換えずに付け足す
+    let file_name = if path.extension().is_some_and(|ext| ext == "bok") {
+        path.to_path_buf()
+    } else {
+        let mut file_name = path.as_os_str().to_owned();
+        file_name.push(".bok");
+        PathBuf::from(file_name)
+    };
+    find_source(root_path, src_path, &file_name, search_paths)
+}
+
+/// `find_file` と同じだが、`.bok` 以外のファイルも探せるよう、`file_name` に拡張子を付け足さない
+pub(crate) fn find_source(
+    root_path: &Path,
+    src_path: &Path,
+    file_name: &Path,
+    search_paths: &[PathBuf],
+) -> Result<PathBuf, Vec<String>> {
+    let dir = src_path.parent().unwrap_or(Path::new(""));
+    let candidates: Vec<_> = std::iter::once(dir)
+        .chain(search_paths.iter().map(PathBuf::as_path))
+        .map(|base| base.join(file_name))
+        .collect();
+    candidates
+        .iter()
+        .filter_map(|candidate| normalize(candidate))
+        .find(|candidate| root_path.join(candidate).is_file())
+        .ok_or_else(|| {
+            candidates
+                .iter()
+                .map(|candidate| match normalize(candidate) {
+                    Some(path) => format!("tried `{}`", path.display()),
+                    None => format!(
+                        "skipped `{}` because it is outside the project root",
+                        candidate.display()
+                    ),
+                })
+                .collect()
+        })
+}
+
+// import 先が見つからないだけなら、ページ自体は出力できる
+fn can_print(file_report: &FileReport) -> bool {
+    file_report
+        .errors
+        .iter()
+        .all(|error| error.code == MISSING_IMPORT)
+}
+
+fn has_missing_import(file_report: &FileReport) -> bool {
+    file_report
+        .errors
+        .iter()
+        .any(|error| error.code == MISSING_IMPORT)
+}
+
+/// 根からの相対パスの '.' と '..' を取り除く。根の外に出るなら None
+pub(crate) fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+// パースと検査を行い、import 先を探す
+fn analyze(
+    root_path: &Path,
+    src_path: &Path,
+    content: &str,
+    search_paths: &[PathBuf],
+) -> (Vec<PathBuf>, FileReport, Document) {
+    let src = Source::new(content);
+    let (mut file_report, document) = analyze_source(&src, root_path, src_path);
+    let (imports, mut errors) =
+        resolve_imports(root_path, src_path, &document.imports, search_paths);
+    file_report.errors.append(&mut errors);
+    (imports, file_report, document)
+}
+
+pub(crate) fn analyze_source(
+    src: &Source,
+    root_path: &Path,
+    src_path: &Path,
+) -> (FileReport, Document) {
+    let mut result = parse::parse_document(src, src.block_range());
+    let dir = root_path.join(src_path.parent().unwrap_or(Path::new("")));
+    let mut warnings = lint::lint_document(src, &result.value, &dir);
+    result.warnings.append(&mut warnings);
+    let document = result.value;
+    (
+        FileReport {
+            path: src_path.to_path_buf(),
+            content: src.text().to_string(),
+            errors: result.errors,
+            warnings: result.warnings,
+        },
+        document,
+    )
+}
 
-    result.value.imports
+// ページの出力に関わる、ファイルごとでない入力のハッシュ
+fn config_hash(config: &BuildConfig, template: &Template) -> String {
+    cache::hash_str(&format!(
+        "{}\0{}\0{}\0{:?}\0{:?}\0{}\0{}",
+        template.html,
+        template.css,
+        template.js,
+        config.render_options,
+        config.page_options,
+        config.keep_going,
+        config.deny_warnings
+    ))
 }
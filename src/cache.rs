@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// 出力ディレクトリに置くビルドのキャッシュ
+pub const CACHE_FILE_NAME: &str = ".bokuteki-cache.json";
+
+/// 前回のビルドの入力の記録
+///
+/// ページの出力は、そのファイルと include や excerpt をしたファイルの中身と、テンプレート・設定・
+/// bokuteki のバージョンだけで決まる。これらが前回と同じファイルは出力し直さなくてよい。
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildManifest {
+    version: String,
+    /// テンプレートと出力に関わる設定のハッシュ
+    config: String,
+    /// 根からの相対パス ('/' 区切り) -> そのファイルの記録
+    files: BTreeMap<String, FileEntry>,
+}
+
+/// 1 つのページの出力に使った入力の記録
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// 中身と、ページの出力に使ったほかのファイルの中身のハッシュ
+    hash: String,
+    dependencies: Vec<String>,
+}
+
+impl FileEntry {
+    /// `dependencies` は include や excerpt をしたファイルの (根からの相対パス, 使った中身)
+    pub fn new(content: &str, dependencies: &[(&Path, &str)]) -> FileEntry {
+        let mut hashed = content.to_string();
+        for (path, content) in dependencies {
+            hashed += &format!("\0{}\0{}", key_of(path), content);
+        }
+        FileEntry {
+            hash: hash_str(&hashed),
+            dependencies: dependencies.iter().map(|(path, _)| key_of(path)).collect(),
+        }
+    }
+}
+
+impl BuildManifest {
+    pub fn new(config: String) -> BuildManifest {
+        BuildManifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            config,
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// 出力ディレクトリから前回の記録を読む。ないか壊れていれば空の記録を返す
+    pub fn load(output_dir: &Path) -> BuildManifest {
+        fs::read_to_string(output_dir.join(CACHE_FILE_NAME))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    /// 前回出力した `src_path` のページが、`current` の設定と `entry` の入力でもそのまま使えるか
+    pub fn is_fresh(&self, current: &BuildManifest, src_path: &Path, entry: &FileEntry) -> bool {
+        self.is_compatible(current)
+            && self
+                .files
+                .get(&key_of(src_path))
+                .is_some_and(|previous| previous.hash == entry.hash)
+    }
+
+    /// 出力したページの入力を記録する
+    pub fn insert(&mut self, src_path: &Path, entry: FileEntry) {
+        self.files.insert(key_of(src_path), entry);
+    }
+
+    /// 出力し直さずに残したページについて、`previous` の記録を引き継ぐ
+    ///
+    /// 残したページは前回までの入力から出力したものなので、今回の入力を記録してはいけない。
+    /// 設定が変わっていれば、前回の記録は今回の設定では使えないので何も記録しない。
+    pub fn carry_over(&mut self, previous: &BuildManifest, src_path: &Path) {
+        let key = key_of(src_path);
+        if let Some(entry) = previous
+            .files
+            .get(&key)
+            .filter(|_| previous.is_compatible(self))
+        {
+            self.files.insert(key, entry.clone());
+        }
+    }
+
+    fn is_compatible(&self, other: &BuildManifest) -> bool {
+        self.version == other.version && self.config == other.config
+    }
+}
+
+fn key_of(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// 64 bit の FNV-1a ハッシュ。Rust のバージョンによらず同じ値になる
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn hash_str(s: &str) -> String {
+    format!("{:016x}", fnv1a(s.as_bytes()))
+}
@@ -0,0 +1,103 @@
+//! プロジェクトの設定ファイル `bokuteki.toml`
+//!
+//! ```toml
+//! entry = "index.bok"        # 複数なら ["lectures/*.bok", "appendix.bok"] のように書く
+//! output = "public"
+//! template = "template"     # 省略するとバイナリに埋め込まれたテンプレートを使う
+//! search-paths = ["lib"]     # import 先を探すディレクトリ
+//! math = "client"           # "katex" もしくは "client"
+//! theme = "dark"
+//! language = "en"
+//! base-url = "https://example.com/notes/"
+//! ```
+
+use crate::print::MathMode;
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = "bokuteki.toml";
+
+/// `bokuteki.toml` の中身。パスは設定ファイルのあるディレクトリからの相対パスに解決済み
+#[derive(Debug, Clone, Default)]
+pub struct ProjectConfig {
+    /// 設定ファイルのあるディレクトリ
+    pub dir: PathBuf,
+    /// 起点となるファイル。glob のパターンを含みうる
+    pub entries: Vec<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub template: Option<PathBuf>,
+    /// import 先を、import したファイルのディレクトリの次に探すディレクトリ
+    pub search_paths: Vec<PathBuf>,
+    pub math: Option<MathMode>,
+    pub theme: Option<String>,
+    pub language: Option<String>,
+    pub base_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RawProjectConfig {
+    entry: Option<OneOrMany>,
+    output: Option<PathBuf>,
+    template: Option<PathBuf>,
+    search_paths: Option<Vec<PathBuf>>,
+    math: Option<String>,
+    theme: Option<String>,
+    language: Option<String>,
+    base_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+/// `start` から親ディレクトリへ順に `bokuteki.toml` を探す
+pub fn discover(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// 設定ファイルを読む
+pub fn load(path: &Path) -> io::Result<ProjectConfig> {
+    let invalid = |message: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), message),
+        )
+    };
+    let raw: RawProjectConfig =
+        toml::from_str(&fs::read_to_string(path)?).map_err(|err| invalid(err.to_string()))?;
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let math = raw
+        .math
+        .map(|math| math.parse::<MathMode>())
+        .transpose()
+        .map_err(invalid)?;
+    Ok(ProjectConfig {
+        entries: match raw.entry {
+            Some(OneOrMany::One(entry)) => vec![dir.join(entry)],
+            Some(OneOrMany::Many(entries)) => entries.iter().map(|entry| dir.join(entry)).collect(),
+            None => vec![],
+        },
+        output: raw.output.map(|output| dir.join(output)),
+        template: raw.template.map(|template| dir.join(template)),
+        search_paths: raw
+            .search_paths
+            .unwrap_or_default()
+            .iter()
+            .map(|search_path| dir.join(search_path))
+            .collect(),
+        math,
+        theme: raw.theme,
+        language: raw.language,
+        base_url: raw.base_url,
+        dir,
+    })
+}
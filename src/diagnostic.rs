@@ -0,0 +1,190 @@
+use crate::document::{InlineRange, Source};
+use std::fmt;
+use std::path::Path;
+
+/// パース中に見つかったエラーもしくは警告
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// 診断の種類を表す短い識別子 (e.g. "unclosed-inline")
+    pub code: &'static str,
+    /// 問題のある箇所
+    pub range: InlineRange,
+    pub message: String,
+    /// 問題に関係する別の箇所
+    pub labels: Vec<Label>,
+    /// 箇所を持たない補足
+    pub notes: Vec<String>,
+}
+
+/// 診断に添える、ソース中の箇所とその説明
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub range: InlineRange,
+    pub message: String,
+}
+
+/// 診断の深刻度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn new(code: &'static str, range: InlineRange, message: impl Into<String>) -> Self {
+        Diagnostic {
+            code,
+            range,
+            message: message.into(),
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn with_label(mut self, range: InlineRange, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            range,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// rustc 風に、該当行と下線をつけて整形する。行と列は 1 始まりで表示する
+    ///
+    /// `color` が true なら ANSI エスケープシーケンスで色をつける。
+    pub fn render(&self, src: &Source, path: &Path, severity: Severity, color: bool) -> String {
+        let style = Style { color };
+        let severity_style = match severity {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        };
+
+        // 下線を引く箇所。主の箇所は '^'、ラベルは '-' で示す
+        let mut marks = vec![(self.range, '^', severity_style, "")];
+        marks.extend(
+            self.labels
+                .iter()
+                .map(|label| (label.range, '-', BLUE, label.message.as_str())),
+        );
+        marks.sort_by_key(|(range, ..)| (range.line, range.start_column));
+
+        let gutter_width = marks
+            .iter()
+            .map(|(range, ..)| (range.line + 1).to_string().len())
+            .max()
+            .unwrap_or(1);
+        let gutter = " ".repeat(gutter_width);
+        let pipe = style.paint(BLUE, "|");
+
+        let mut out = format!(
+            "{}{}\n{}{} {}:{}:{}\n{} {}\n",
+            style.paint(
+                severity_style,
+                &format!("{}[{}]", severity.as_str(), self.code)
+            ),
+            style.paint(BOLD, &format!(": {}", self.message)),
+            gutter,
+            style.paint(BLUE, "-->"),
+            path.display(),
+            self.range.line + 1,
+            src.char_column(self.range.line, self.range.start_column) + 1,
+            gutter,
+            pipe,
+        );
+        let mut previous_line = None;
+        for (range, mark, mark_style, message) in marks {
+            if previous_line != Some(range.line) {
+                if previous_line.is_some_and(|line| line + 1 < range.line) {
+                    out += &format!("{}\n", style.paint(BLUE, "..."));
+                }
+                let line_number = format!("{:>width$} |", range.line + 1, width = gutter_width);
+                out += &format!(
+                    "{} {}\n",
+                    style.paint(BLUE, &line_number),
+                    src.line(range.line).unwrap_or("")
+                );
+                previous_line = Some(range.line);
+            }
+            let start = src.char_column(range.line, range.start_column);
+            let end = src.char_column(range.line, range.end_column);
+            let underline = mark.to_string().repeat((end - start).max(1));
+            let underline = if message.is_empty() {
+                underline
+            } else {
+                format!("{} {}", underline, message)
+            };
+            out += &format!(
+                "{} {} {}{}\n",
+                gutter,
+                pipe,
+                " ".repeat(start),
+                style.paint(mark_style, &underline)
+            );
+        }
+        if !self.notes.is_empty() {
+            out += &format!("{} {}\n", gutter, pipe);
+        }
+        for note in &self.notes {
+            out += &format!(
+                "{} {} {}: {}\n",
+                gutter,
+                style.paint(BLUE, "="),
+                style.paint(BOLD, "note"),
+                note
+            );
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "at {}:{}: {}",
+            self.range.line, self.range.start_column, self.message
+        )?;
+        for label in &self.labels {
+            write!(
+                f,
+                " (at {}:{}: {})",
+                label.range.line, label.range.start_column, label.message
+            )?;
+        }
+        Ok(())
+    }
+}
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", code, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
@@ -1,15 +1,27 @@
 use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::ops::Range;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Document {
     pub preamble: HashMap<String, String>,
     pub block_elements: Vec<BlockElement>,
-    pub imports: Vec<PathBuf>,
+    pub imports: Vec<Import>,
 }
 
-// NOTE: 新しく BlockElement の種類を追加する場合は `parse::parse_paragraph` 関数内の `is_paragraph_end` 関数を修正すること
+/// `import 'path';` の 1 行
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// 書かれたとおりのパス。拡張子は含まない場合がある
+    pub path: PathBuf,
+    /// import の行
+    pub range: InlineRange,
+}
+
+// NOTE: 新しく BlockElement の種類を追加する場合は `parse` モジュールの `LineKind` と `classify_line` 関数、
+//       `parse_paragraph` 関数内の段落の終わりを判定する match、`visit` モジュールの `walk_*` 関数を修正すること
 #[derive(Debug, PartialEq, Eq)]
 pub enum BlockElement {
     Heading {
@@ -21,6 +33,10 @@ pub enum BlockElement {
     },
     Code {
         lines: BlockRange,
+        /// 言語の名前。ファイルからの抜粋では拡張子から決める
+        language: Option<String>,
+        /// `excerpt 'path';` で書かれた、ファイルからの抜粋。Some なら `lines` は空で、中身は抜粋になる
+        excerpt: Option<Excerpt>,
     },
     Math {
         lines: BlockRange,
@@ -41,7 +57,70 @@ pub enum BlockElement {
     Blockquote {
         inner: Vec<BlockElement>,
     },
-    ParseError,
+    /// `include 'path';` の行。取り込んだブロック要素はビルド時に `Include::content` に入る
+    Include(Include),
+    /// パースに失敗した行。診断はエラーとして別に報告される
+    ParseError {
+        lines: BlockRange,
+        message: String,
+    },
+}
+
+/// 別のファイルのブロック要素をその場に取り込む行
+///
+/// `include 'path#selector';` のように書くと、文字列が `selector` の見出し
+/// (次の同じ深さ以上の見出しまで) もしくはタイトルが `selector` の定理だけを取り込む。
+#[derive(Debug, PartialEq, Eq)]
+pub struct Include {
+    /// 書かれたとおりのパス。拡張子は含まない場合がある
+    pub path: PathBuf,
+    pub selector: Option<String>,
+    /// include の行
+    pub range: InlineRange,
+    /// 取り込んだブロック要素。パースしただけでは None で、取り込めなかった場合も None のまま
+    pub content: Option<IncludedBlocks>,
+}
+
+/// 取り込んだファイルのうち、`blocks` の範囲のトップレベルのブロック要素
+///
+/// ブロック要素の range は取り込んだファイルの中身を指す。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludedBlocks {
+    pub file: Arc<IncludedFile>,
+    pub blocks: Range<usize>,
+}
+
+impl IncludedBlocks {
+    pub fn block_elements(&self) -> &[BlockElement] {
+        &self.file.block_elements[self.blocks.clone()]
+    }
+}
+
+/// 取り込んだファイル。中の include も展開済み
+#[derive(Debug, PartialEq, Eq)]
+pub struct IncludedFile {
+    /// プロジェクトの根からの相対パス
+    pub path: PathBuf,
+    pub text: String,
+    pub block_elements: Vec<BlockElement>,
+}
+
+/// ファイルの一部をコードブロックとして抜き出す行
+///
+/// `excerpt 'src/lib.rs#selector';` の `selector` には、行の範囲 (`10-20`)、
+/// `ANCHOR: name` と `ANCHOR_END: name` を含む行で囲まれた部分の名前 (`name`)、
+/// 関数 (`fn name`) のどれかを書ける。省略するとファイル全体を抜き出す。
+#[derive(Debug, PartialEq, Eq)]
+pub struct Excerpt {
+    /// 書かれたとおりのパス。excerpt を書いたファイルのディレクトリ、検索パスの順に探す
+    pub path: PathBuf,
+    pub selector: Option<String>,
+    /// excerpt の行
+    pub range: InlineRange,
+    /// 抜き出したファイルのプロジェクトの根からの相対パス。パースしただけのときや、見つからなかったときは None
+    pub source: Option<PathBuf>,
+    /// 抜き出した中身。パースしただけのときや、抜き出せなかったときは None
+    pub text: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -65,7 +144,7 @@ pub enum TheoremKind {
     ParseError,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ListMarkKind {
     Bullet,
 }
@@ -94,15 +173,20 @@ pub enum InlineElement {
     SmallCaps {
         range: InlineRange,
     },
-    ParseError,
+    /// パースに失敗した範囲。診断はエラーもしくは警告として別に報告される
+    ParseError {
+        range: InlineRange,
+        message: String,
+    },
 }
 
 impl InlineElement {
     pub fn is_parse_error(&self) -> bool {
-        self == &InlineElement::ParseError
+        matches!(self, InlineElement::ParseError { .. })
     }
 }
 
+/// 1 行の中の範囲。列は行頭からのバイトオフセット
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct InlineRange {
     pub line: usize,
@@ -111,17 +195,12 @@ pub struct InlineRange {
 }
 
 impl InlineRange {
+    /// 先頭を `n` バイト進める。終端を越える分は切り捨てる
     pub fn consume(mut self, n: usize) -> Self {
-        self.start_column += n;
-        assert!(self.start_column <= self.end_column);
+        self.start_column = self.start_column.saturating_add(n).min(self.end_column);
         self
     }
 
-    pub fn move_to_next_char(&mut self) {
-        self.start_column += 1;
-        assert!(self.start_column <= self.end_column);
-    }
-
     pub fn is_empty(&self) -> bool {
         self.start_column == self.end_column
     }
@@ -129,13 +208,123 @@ impl InlineRange {
 
 pub type BlockRange = VecDeque<InlineRange>;
 
-pub fn src_block_range(src: &Vec<Vec<char>>) -> BlockRange {
-    src.iter()
-        .enumerate()
-        .map(|(line_idx, line)| InlineRange {
-            line: line_idx,
-            start_column: 0,
-            end_column: line.len(),
-        })
-        .collect()
+/// パース対象のソース。`Document` 中の各 range はこのソースを指す
+///
+/// 元の文字列を借用したまま、各行の先頭位置だけを持つ。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source<'a> {
+    text: &'a str,
+    // 各行の先頭のバイトオフセットと、改行文字を除いた行の中身
+    lines: Vec<(usize, &'a str)>,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let mut lines = vec![];
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            let content = line.strip_suffix('\n').unwrap_or(line);
+            let content = content.strip_suffix('\r').unwrap_or(content);
+            lines.push((offset, content));
+            offset += line.len();
+        }
+        Source { text, lines }
+    }
+
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// 改行文字を除いた行の中身
+    pub fn line(&self, line: usize) -> Option<&'a str> {
+        self.lines.get(line).map(|(_, content)| *content)
+    }
+
+    /// ソース全体を指す range
+    pub fn block_range(&self) -> BlockRange {
+        self.lines
+            .iter()
+            .enumerate()
+            .map(|(line_idx, (_, content))| InlineRange {
+                line: line_idx,
+                start_column: 0,
+                end_column: content.len(),
+            })
+            .collect()
+    }
+
+    /// range の先頭の文字。range が空なら None
+    pub fn pick_char(&self, range: &InlineRange) -> Option<char> {
+        if range.is_empty() {
+            return None;
+        }
+        self.line(range.line)
+            .and_then(|line| line.get(range.start_column..))
+            .and_then(|rest| rest.chars().next())
+    }
+
+    /// range の先頭を 1 文字進める。文字がなければ range を空にする
+    pub fn move_to_next_char(&self, range: &mut InlineRange) {
+        match self.pick_char(range) {
+            Some(c) => *range = range.consume(c.len_utf8()),
+            None => range.start_column = range.end_column,
+        }
+    }
+
+    /// range が指す文字列を得る
+    pub fn slice(&self, range: &InlineRange) -> &'a str {
+        self.line(range.line)
+            .and_then(|line| line.get(range.start_column..range.end_column))
+            .unwrap_or("")
+    }
+
+    /// 見出しや定理のタイトルなどの、装飾を除いた文字列。前後の空白は除く
+    pub fn plain_text(&self, inline_elements: &[InlineElement]) -> String {
+        let mut text = String::new();
+        for inline_element in inline_elements {
+            match inline_element {
+                InlineElement::Text { range }
+                | InlineElement::Code { range }
+                | InlineElement::Math { range }
+                | InlineElement::SmallCaps { range }
+                | InlineElement::ParseError { range, .. } => text += self.slice(range),
+                InlineElement::Link { text: inner, .. } => text += &self.plain_text(inner),
+            }
+        }
+        text.trim().to_string()
+    }
+
+    /// ソース先頭からのバイトオフセット
+    pub fn offset(&self, line: usize, column: usize) -> usize {
+        match self.lines.get(line) {
+            Some((line_start, _)) => line_start + column,
+            None => self.text.len(),
+        }
+    }
+
+    /// 行頭から `column` バイト目までの文字数
+    pub fn char_column(&self, line: usize, column: usize) -> usize {
+        self.line_prefix(line, column).chars().count()
+    }
+
+    /// 行頭から `column` バイト目までを UTF-16 で数えた長さ (LSP の位置表現)
+    pub fn utf16_column(&self, line: usize, column: usize) -> usize {
+        self.line_prefix(line, column).encode_utf16().count()
+    }
+
+    fn line_prefix(&self, line: usize, column: usize) -> &'a str {
+        self.line(line)
+            .map(|content| {
+                let mut column = column.min(content.len());
+                while !content.is_char_boundary(column) {
+                    column -= 1;
+                }
+                &content[..column]
+            })
+            .unwrap_or("")
+    }
 }
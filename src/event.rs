@@ -0,0 +1,332 @@
+use crate::document::*;
+use crate::parse::{self, Error, Warning};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+/// 入れ子になった要素の種類。`Event::Start` と `Event::End` の組で要素の始まりと終わりを表す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tag {
+    Heading {
+        level: usize,
+    },
+    Paragraph,
+    /// 中身は 1 行ごとの `Event::Text`
+    CodeBlock,
+    /// 中身は 1 行ごとの `Event::Text`
+    MathBlock,
+    Theorem {
+        kind: TheoremKind,
+    },
+    TheoremTitle,
+    Proof,
+    Derivation,
+    /// 前提 (`InferenceRule` もしくは `Leaf`) が並んだあとに `Conclusion` と `RuleName` が続く
+    InferenceRule,
+    Conclusion,
+    RuleName,
+    Leaf,
+    List {
+        mark_kind: ListMarkKind,
+    },
+    /// 先頭行のインライン要素のあとに子ブロックが続く
+    ListItem,
+    Blockquote,
+    Link {
+        url_range: InlineRange,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Preamble {
+        key: String,
+        value: String,
+    },
+    Import(PathBuf),
+    /// `include` の行。取り込む中身はイベントにならない
+    Include {
+        path: PathBuf,
+        selector: Option<String>,
+    },
+    /// `excerpt` の行。抜き出す中身はイベントにならない
+    Excerpt {
+        path: PathBuf,
+        selector: Option<String>,
+    },
+    Start(Tag),
+    End(Tag),
+    Text(InlineRange),
+    Code(InlineRange),
+    Math(InlineRange),
+    SmallCaps(InlineRange),
+    /// パースに失敗した行。診断は `Parser::errors` もしくは `Parser::warnings` で得られる
+    BlockParseError {
+        lines: BlockRange,
+        message: String,
+    },
+    /// パースに失敗したインラインの範囲。診断は `Parser::errors` もしくは `Parser::warnings` で得られる
+    InlineParseError {
+        range: InlineRange,
+        message: String,
+    },
+}
+
+enum State {
+    Preamble,
+    Imports,
+    Blocks,
+}
+
+/// Bokuteki 文書をイベント列として読むパーサ
+///
+/// トップレベルのブロック要素を 1 つずつパースしてはイベント列に展開するので、
+/// 文書全体の木を作らずに要素を走査できる。
+///
+/// ```
+/// use bokuteki::{Event, Parser, Source, Tag};
+///
+/// let src = Source::new("Thm. Foo\n  $x = x$\n\nproof.\n  trivial");
+/// let theorem_count = Parser::new(&src)
+///     .filter(|event| matches!(event, Event::Start(Tag::Theorem { .. })))
+///     .count();
+/// assert_eq!(theorem_count, 1);
+/// ```
+pub struct Parser<'a> {
+    src: &'a Source<'a>,
+    rest_range: BlockRange,
+    state: State,
+    events: VecDeque<Event>,
+    errors: Vec<Error>,
+    warnings: Vec<Warning>,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(src: &'a Source<'a>) -> Self {
+        Parser {
+            src,
+            rest_range: src.block_range(),
+            state: State::Preamble,
+            events: VecDeque::new(),
+            errors: vec![],
+            warnings: vec![],
+        }
+    }
+
+    /// これまでに読んだ部分のエラー
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    /// これまでに読んだ部分の警告
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    // 1 行もしくはトップレベルのブロック要素 1 つを読み進めてイベントを積む
+    // ソース終端に達していたら false を返す
+    fn fill(&mut self) -> bool {
+        match self.state {
+            State::Preamble => match self.rest_range.front() {
+                Some(line) if parse::is_preamble_line(self.src, *line) => {
+                    let mut result = parse::parse_preamble(self.src, *line);
+                    self.errors.append(&mut result.errors);
+                    self.warnings.append(&mut result.warnings);
+                    let (key, value) = result.value;
+                    self.events.push_back(Event::Preamble { key, value });
+                    self.rest_range.pop_front();
+                    true
+                }
+                Some(_) => {
+                    self.state = State::Imports;
+                    true
+                }
+                None => false,
+            },
+            State::Imports => match self.rest_range.front() {
+                Some(line) if parse::is_import_line(self.src, *line) => {
+                    let mut result = parse::parse_import(self.src, *line);
+                    if result.errors.is_empty() {
+                        self.events.push_back(Event::Import(result.value));
+                    }
+                    self.errors.append(&mut result.errors);
+                    self.warnings.append(&mut result.warnings);
+                    self.rest_range.pop_front();
+                    true
+                }
+                Some(_) => {
+                    self.state = State::Blocks;
+                    true
+                }
+                None => false,
+            },
+            State::Blocks => {
+                // 空行は無視する
+                while let Some(line) = self.rest_range.front() {
+                    if line.is_empty() {
+                        self.rest_range.pop_front();
+                    } else {
+                        break;
+                    }
+                }
+                if self.rest_range.is_empty() {
+                    return false;
+                }
+
+                let rest_range = std::mem::take(&mut self.rest_range);
+                let mut result = parse::parse_block_element(self.src, rest_range);
+                self.errors.append(&mut result.errors);
+                self.warnings.append(&mut result.warnings);
+                self.rest_range = result.rest_range;
+                push_block_element_events(&mut self.events, result.value);
+                true
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Parser<'a> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(event);
+            }
+            if !self.fill() {
+                return None;
+            }
+        }
+    }
+}
+
+fn push_block_element_events(events: &mut VecDeque<Event>, block_element: BlockElement) {
+    match block_element {
+        BlockElement::Heading { level, content } => {
+            let tag = Tag::Heading { level };
+            events.push_back(Event::Start(tag.clone()));
+            push_inline_elements_events(events, content);
+            events.push_back(Event::End(tag));
+        }
+        BlockElement::Paragraph { content } => {
+            events.push_back(Event::Start(Tag::Paragraph));
+            push_inline_elements_events(events, content);
+            events.push_back(Event::End(Tag::Paragraph));
+        }
+        BlockElement::Code {
+            excerpt: Some(excerpt),
+            ..
+        } => events.push_back(Event::Excerpt {
+            path: excerpt.path,
+            selector: excerpt.selector,
+        }),
+        BlockElement::Code { lines, .. } => {
+            events.push_back(Event::Start(Tag::CodeBlock));
+            events.extend(lines.into_iter().map(Event::Text));
+            events.push_back(Event::End(Tag::CodeBlock));
+        }
+        BlockElement::Math { lines } => {
+            events.push_back(Event::Start(Tag::MathBlock));
+            events.extend(lines.into_iter().map(Event::Text));
+            events.push_back(Event::End(Tag::MathBlock));
+        }
+        BlockElement::Theorem {
+            kind,
+            title,
+            content,
+        } => {
+            let tag = Tag::Theorem { kind };
+            events.push_back(Event::Start(tag.clone()));
+            events.push_back(Event::Start(Tag::TheoremTitle));
+            push_inline_elements_events(events, title);
+            events.push_back(Event::End(Tag::TheoremTitle));
+            push_block_elements_events(events, content);
+            events.push_back(Event::End(tag));
+        }
+        BlockElement::Proof { content } => {
+            events.push_back(Event::Start(Tag::Proof));
+            push_block_elements_events(events, content);
+            events.push_back(Event::End(Tag::Proof));
+        }
+        BlockElement::Derivation(derivation) => {
+            events.push_back(Event::Start(Tag::Derivation));
+            push_derivation_events(events, derivation);
+            events.push_back(Event::End(Tag::Derivation));
+        }
+        BlockElement::List { mark_kind, items } => {
+            let tag = Tag::List { mark_kind };
+            events.push_back(Event::Start(tag.clone()));
+            for item in items {
+                events.push_back(Event::Start(Tag::ListItem));
+                push_inline_elements_events(events, item.top_line);
+                push_block_elements_events(events, item.blocks);
+                events.push_back(Event::End(Tag::ListItem));
+            }
+            events.push_back(Event::End(tag));
+        }
+        BlockElement::Blockquote { inner } => {
+            events.push_back(Event::Start(Tag::Blockquote));
+            push_block_elements_events(events, inner);
+            events.push_back(Event::End(Tag::Blockquote));
+        }
+        BlockElement::Include(include) => events.push_back(Event::Include {
+            path: include.path,
+            selector: include.selector,
+        }),
+        BlockElement::ParseError { lines, message } => {
+            events.push_back(Event::BlockParseError { lines, message })
+        }
+    }
+}
+
+fn push_block_elements_events(events: &mut VecDeque<Event>, block_elements: Vec<BlockElement>) {
+    for block_element in block_elements {
+        push_block_element_events(events, block_element);
+    }
+}
+
+fn push_derivation_events(events: &mut VecDeque<Event>, derivation: Derivation) {
+    match derivation {
+        Derivation::InferenceRule {
+            premises,
+            conclusion,
+            rule_name,
+        } => {
+            events.push_back(Event::Start(Tag::InferenceRule));
+            for premise in premises {
+                push_derivation_events(events, premise);
+            }
+            events.push_back(Event::Start(Tag::Conclusion));
+            push_inline_elements_events(events, conclusion);
+            events.push_back(Event::End(Tag::Conclusion));
+            events.push_back(Event::Start(Tag::RuleName));
+            push_inline_elements_events(events, rule_name);
+            events.push_back(Event::End(Tag::RuleName));
+            events.push_back(Event::End(Tag::InferenceRule));
+        }
+        Derivation::Leaf(inline_elements) => {
+            events.push_back(Event::Start(Tag::Leaf));
+            push_inline_elements_events(events, inline_elements);
+            events.push_back(Event::End(Tag::Leaf));
+        }
+    }
+}
+
+fn push_inline_elements_events(events: &mut VecDeque<Event>, inline_elements: Vec<InlineElement>) {
+    for inline_element in inline_elements {
+        match inline_element {
+            InlineElement::Text { range } => events.push_back(Event::Text(range)),
+            InlineElement::Link { text, url_range } => {
+                let tag = Tag::Link { url_range };
+                events.push_back(Event::Start(tag.clone()));
+                push_inline_elements_events(events, text);
+                events.push_back(Event::End(tag));
+            }
+            InlineElement::Code { range } => events.push_back(Event::Code(range)),
+            InlineElement::Math { range } => events.push_back(Event::Math(range)),
+            InlineElement::SmallCaps { range } => events.push_back(Event::SmallCaps(range)),
+            InlineElement::ParseError { range, message } => {
+                events.push_back(Event::InlineParseError { range, message })
+            }
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! ファイルからのコードの抜き出し (`excerpt 'path#selector';`)
+
+/// 抜き出す範囲の始まりと終わりの目印。コメントの書き方によらず、行に含まれていればよい
+const ANCHOR_START: &str = "ANCHOR:";
+const ANCHOR_END: &str = "ANCHOR_END:";
+
+/// `text` から `selector` の部分を抜き出す。見つからなければその理由を返す
+///
+/// 抜き出した部分からは目印の行を除き、共通の字下げを取り除く。
+pub fn select(text: &str, selector: Option<&str>) -> Result<String, String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let selected = match selector.map(str::trim) {
+        None => &lines[..],
+        Some(selector) => {
+            if let Some(range) = parse_line_range(selector) {
+                select_lines(&lines, range)?
+            } else if let Some(name) = selector.strip_prefix("fn ") {
+                select_function(&lines, name.trim())?
+            } else {
+                select_anchor(&lines, selector)?
+            }
+        }
+    };
+    let selected: Vec<&str> = selected
+        .iter()
+        .copied()
+        .filter(|line| !line.contains(ANCHOR_START) && !line.contains(ANCHOR_END))
+        .collect();
+    Ok(dedent(&selected))
+}
+
+// "10-20"、"10-"、"10" を 1 始まりの (最初の行, 最後の行) にする。最後の行が None なら末尾まで
+fn parse_line_range(selector: &str) -> Option<(usize, Option<usize>)> {
+    let (start, end) = match selector.split_once('-') {
+        Some((start, "")) => (start, None),
+        Some((start, end)) => (start, Some(end.parse().ok()?)),
+        None => (selector, Some(selector.parse().ok()?)),
+    };
+    Some((start.parse().ok()?, end))
+}
+
+fn select_lines<'a, 'b>(
+    lines: &'b [&'a str],
+    (start, end): (usize, Option<usize>),
+) -> Result<&'b [&'a str], String> {
+    let end = end.unwrap_or(lines.len());
+    if start == 0 || start > end || end > lines.len() {
+        return Err(format!(
+            "lines {}-{} are out of the file ({} lines)",
+            start,
+            end,
+            lines.len()
+        ));
+    }
+    Ok(&lines[start - 1..end])
+}
+
+fn select_anchor<'a, 'b>(lines: &'b [&'a str], name: &str) -> Result<&'b [&'a str], String> {
+    let is_marker = |line: &str, marker: &str| {
+        line.split_once(marker)
+            .is_some_and(|(_, rest)| rest.split_whitespace().next() == Some(name))
+    };
+    let start = lines
+        .iter()
+        .position(|line| is_marker(line, ANCHOR_START))
+        .ok_or_else(|| format!("marker `{} {}` is not found", ANCHOR_START, name))?;
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| is_marker(line, ANCHOR_END))
+        .ok_or_else(|| format!("marker `{} {}` is not found", ANCHOR_END, name))?;
+    Ok(&lines[start + 1..start + 1 + end])
+}
+
+// 関数の定義の行から、波括弧が閉じるまで (波括弧がなければ字下げが戻るまで) を抜き出す
+fn select_function<'a, 'b>(lines: &'b [&'a str], name: &str) -> Result<&'b [&'a str], String> {
+    let start = lines
+        .iter()
+        .position(|line| {
+            ["fn ", "def ", "function "].iter().any(|keyword| {
+                line.match_indices(keyword).any(|(index, _)| {
+                    let is_word_start = line[..index]
+                        .chars()
+                        .next_back()
+                        .is_none_or(|c| !c.is_alphanumeric() && c != '_');
+                    let rest = &line[index + keyword.len()..];
+                    is_word_start
+                        && rest.strip_prefix(name).is_some_and(|after| {
+                            !after.starts_with(|c: char| c.is_alphanumeric() || c == '_')
+                        })
+                })
+            })
+        })
+        .ok_or_else(|| format!("function `{}` is not found", name))?;
+
+    let mut depth = 0;
+    let mut opened = false;
+    for (offset, line) in lines[start..].iter().enumerate() {
+        for c in line.chars() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    opened = true;
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+        }
+        if opened && depth <= 0 {
+            return Ok(&lines[start..=start + offset]);
+        }
+        // 波括弧のない言語 (Python など) は、定義の行より深く字下げされた行までとする
+        if !opened && line.trim_end().ends_with(':') {
+            let indent = indent_of(lines[start]);
+            let body = lines[start + offset + 1..]
+                .iter()
+                .take_while(|line| line.trim().is_empty() || indent_of(line) > indent)
+                .count();
+            let mut end = start + offset + 1 + body;
+            while end > start + offset + 1 && lines[end - 1].trim().is_empty() {
+                end -= 1;
+            }
+            return Ok(&lines[start..end]);
+        }
+    }
+    Err(format!("the end of function `{}` is not found", name))
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+// 空行以外に共通する字下げを取り除いて、行をつなげる
+fn dedent(lines: &[&str]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| indent_of(line))
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| line.get(indent..).unwrap_or("").trim_end())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,355 @@
+//! `include 'path';` の展開と `excerpt 'path';` の抜き出し
+//!
+//! 取り込むファイルは 1 度だけ読んでパースし、展開したブロック要素を `Arc` で共有する。
+//! 取り込んだファイルの中の診断は、そのファイル自身のパスと位置で報告する。
+
+use crate::build::{self, FileReport};
+use crate::diagnostic::Diagnostic;
+use crate::document::*;
+use crate::excerpt;
+use crate::lint::{self, PageLink};
+use crate::parse::{Error, Warning};
+use crate::visit::{self, Field, NodePath, Visit, VisitMut};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const MISSING_INCLUDE: &str = "missing-include";
+const INCLUDE_CYCLE: &str = "include-cycle";
+const UNKNOWN_INCLUDE_SELECTOR: &str = "unknown-include-selector";
+const MISSING_EXCERPT: &str = "missing-excerpt";
+
+/// include の展開や excerpt の抜き出しに失敗したことによるエラーがあるか
+pub fn has_expand_error(file_report: &FileReport) -> bool {
+    file_report.errors.iter().any(|error| {
+        [
+            MISSING_INCLUDE,
+            INCLUDE_CYCLE,
+            UNKNOWN_INCLUDE_SELECTOR,
+            MISSING_EXCERPT,
+        ]
+        .contains(&error.code)
+    })
+}
+
+pub struct Includer<'a> {
+    root_path: &'a Path,
+    search_paths: &'a [PathBuf],
+    deny_warnings: bool,
+    // 根からの相対パス -> 展開したファイル。展開している途中なら None
+    files: HashMap<PathBuf, Option<Arc<IncludedFile>>>,
+    // 取り込んだファイルの結果。初めて取り込んだ順に並べる
+    reports: Vec<FileReport>,
+    // 抜き出したファイルの根からの相対パス -> 中身。読めなければ None
+    excerpt_files: HashMap<PathBuf, Option<String>>,
+    // 抜き出したファイル。初めて抜き出した順に並べる
+    excerpt_order: Vec<PathBuf>,
+    // 取り込んだファイルに書かれた、別のページへのリンク
+    links: HashMap<PathBuf, Vec<PageLink>>,
+}
+
+/// 展開を終えた結果
+pub struct Expanded {
+    /// 取り込んだファイルの結果。初めて取り込んだ順に並べる
+    pub reports: Vec<FileReport>,
+    /// 抜き出したファイルの根からの相対パス。初めて抜き出した順に並べる
+    pub excerpt_files: Vec<PathBuf>,
+    /// 取り込んだファイルの根からの相対パス -> そのファイルに書かれた、別のページへのリンク
+    pub links: HashMap<PathBuf, Vec<PageLink>>,
+}
+
+impl<'a> Includer<'a> {
+    pub fn new(root_path: &'a Path, search_paths: &'a [PathBuf], deny_warnings: bool) -> Self {
+        Includer {
+            root_path,
+            search_paths,
+            deny_warnings,
+            files: HashMap::new(),
+            reports: vec![],
+            excerpt_files: HashMap::new(),
+            excerpt_order: vec![],
+            links: HashMap::new(),
+        }
+    }
+
+    /// `file_report` のファイルの文書中の include を展開して excerpt を抜き出し、それらの行に対する診断を
+    /// `file_report` に加える
+    pub fn expand(&mut self, file_report: &mut FileReport, block_elements: &mut [BlockElement]) {
+        let src_path = file_report.path.as_path();
+        // 自分自身を取り込む循環を見つけられるよう、展開中の印をつけておく
+        let marked = !self.files.contains_key(src_path);
+        if marked {
+            self.files.insert(src_path.to_path_buf(), None);
+        }
+        let mut expander = Expander {
+            includer: self,
+            src_path,
+            errors: vec![],
+            warnings: vec![],
+        };
+        visit::walk_block_elements_mut(
+            &mut expander,
+            block_elements,
+            Field::BlockElements,
+            &mut NodePath::default(),
+        );
+        let Expander {
+            mut errors,
+            mut warnings,
+            ..
+        } = expander;
+        if marked {
+            self.files.remove(src_path);
+        }
+        file_report.errors.append(&mut errors);
+        if self.deny_warnings {
+            file_report.errors.append(&mut warnings);
+        } else {
+            file_report.warnings.append(&mut warnings);
+        }
+    }
+
+    pub fn finish(self) -> Expanded {
+        Expanded {
+            reports: self.reports,
+            excerpt_files: self.excerpt_order,
+            links: self.links,
+        }
+    }
+
+    fn include(&mut self, src_path: &Path, include: &Include) -> Result<IncludedBlocks, Error> {
+        let path = build::find_file(self.root_path, src_path, &include.path, self.search_paths)
+            .map_err(|notes| {
+                notes.into_iter().fold(
+                    Diagnostic::new(
+                        MISSING_INCLUDE,
+                        include.range,
+                        format!("included file '{}' is not found.", include.path.display()),
+                    ),
+                    Diagnostic::with_note,
+                )
+            })?;
+        let file = match self.files.get(&path) {
+            Some(Some(file)) => file.clone(),
+            Some(None) => {
+                return Err(Diagnostic::new(
+                    INCLUDE_CYCLE,
+                    include.range,
+                    format!("'{}' is included recursively.", path.display()),
+                ))
+            }
+            None => self.load(&path).map_err(|err| {
+                Diagnostic::new(
+                    MISSING_INCLUDE,
+                    include.range,
+                    format!("cannot read '{}': {}", path.display(), err),
+                )
+            })?,
+        };
+        let blocks = match &include.selector {
+            Some(selector) => select(&file, selector).ok_or_else(|| {
+                Diagnostic::new(
+                    UNKNOWN_INCLUDE_SELECTOR,
+                    include.range,
+                    format!(
+                        "'{}' has no heading or theorem titled '{}'.",
+                        path.display(),
+                        selector
+                    ),
+                )
+            })?,
+            None => 0..file.block_elements.len(),
+        };
+        Ok(IncludedBlocks { file, blocks })
+    }
+
+    // ファイルを読んでパース・検査し、中の include も展開する
+    fn load(&mut self, path: &Path) -> std::io::Result<Arc<IncludedFile>> {
+        let text = fs::read_to_string(self.root_path.join(path))?;
+        let src = Source::new(&text);
+        let (mut file_report, document) = build::analyze_source(&src, self.root_path, path);
+        if self.deny_warnings {
+            file_report.errors.append(&mut file_report.warnings);
+        }
+        // 中で include しているファイルより先に並べる
+        let index = self.reports.len();
+        self.reports.push(FileReport {
+            path: path.to_path_buf(),
+            content: String::new(),
+            errors: vec![],
+            warnings: vec![],
+        });
+        let mut block_elements = document.block_elements;
+        self.expand(&mut file_report, &mut block_elements);
+        self.reports[index] = file_report;
+        let links = lint::page_links(&src, path, &block_elements);
+        self.links.insert(path.to_path_buf(), links);
+        let file = Arc::new(IncludedFile {
+            path: path.to_path_buf(),
+            text,
+            block_elements,
+        });
+        self.files.insert(path.to_path_buf(), Some(file.clone()));
+        Ok(file)
+    }
+
+    // `excerpt` の中身を抜き出す。ファイルが読めなければエラー、範囲が見つからなければ警告にする
+    fn excerpt(&mut self, src_path: &Path, excerpt: &mut Excerpt) -> Result<(), Error> {
+        // include と同じく、根の外のファイルは読まない
+        let source = build::find_source(self.root_path, src_path, &excerpt.path, self.search_paths)
+            .map_err(|notes| {
+                notes.into_iter().fold(
+                    Diagnostic::new(
+                        MISSING_EXCERPT,
+                        excerpt.range,
+                        format!("excerpted file '{}' is not found.", excerpt.path.display()),
+                    ),
+                    Diagnostic::with_note,
+                )
+            })?;
+        let root_path = self.root_path;
+        let text = self
+            .excerpt_files
+            .entry(source.clone())
+            .or_insert_with(|| fs::read_to_string(root_path.join(&source)).ok());
+        let Some(text) = text else {
+            return Err(Diagnostic::new(
+                MISSING_EXCERPT,
+                excerpt.range,
+                format!("cannot read '{}'.", source.display()),
+            ));
+        };
+        if !self.excerpt_order.contains(&source) {
+            self.excerpt_order.push(source.clone());
+        }
+        excerpt.source = Some(source.clone());
+        match excerpt::select(text, excerpt.selector.as_deref()) {
+            Ok(selected) => {
+                excerpt.text = Some(selected);
+                Ok(())
+            }
+            Err(reason) => Err(Diagnostic::new(
+                "excerpt-not-found",
+                excerpt.range,
+                format!("{} in '{}'.", reason, source.display()),
+            )),
+        }
+    }
+}
+
+struct Expander<'a, 'b> {
+    includer: &'a mut Includer<'b>,
+    src_path: &'a Path,
+    errors: Vec<Error>,
+    warnings: Vec<Warning>,
+}
+
+impl VisitMut for Expander<'_, '_> {
+    fn visit_block_element_mut(&mut self, block_element: &mut BlockElement, path: &mut NodePath) {
+        match block_element {
+            BlockElement::Include(include) => match self.includer.include(self.src_path, include) {
+                Ok(content) => include.content = Some(content),
+                Err(error) => self.errors.push(error),
+            },
+            BlockElement::Code {
+                excerpt: Some(excerpt),
+                ..
+            } => {
+                if let Err(diagnostic) = self.includer.excerpt(self.src_path, excerpt) {
+                    // 範囲が見つからないだけなら、空のコードブロックにしてページは出力する
+                    if diagnostic.code == MISSING_EXCERPT {
+                        self.errors.push(diagnostic);
+                    } else {
+                        self.warnings.push(diagnostic);
+                    }
+                }
+            }
+            _ => {}
+        }
+        visit::walk_block_element_mut(self, block_element, path);
+    }
+}
+
+// `selector` の見出しの節もしくは定理の、トップレベルのブロック要素での範囲
+fn select(file: &IncludedFile, selector: &str) -> Option<std::ops::Range<usize>> {
+    let src = Source::new(&file.text);
+    let selector = selector.trim();
+    let block_elements = &file.block_elements;
+    block_elements
+        .iter()
+        .enumerate()
+        .find_map(|(index, block_element)| match block_element {
+            BlockElement::Heading { level, content } if src.plain_text(content) == selector => {
+                // 次の同じ深さ以上の見出しまでを節とする
+                let end = block_elements[index + 1..]
+                    .iter()
+                    .position(|block_element| {
+                        matches!(block_element, BlockElement::Heading { level: next, .. } if next <= level)
+                    })
+                    .map_or(block_elements.len(), |offset| index + 1 + offset);
+                Some(index..end)
+            }
+            BlockElement::Theorem { title, .. } if src.plain_text(title) == selector => {
+                Some(index..index + 1)
+            }
+            _ => None,
+        })
+}
+
+/// ページの出力に使った、取り込んだファイルと抜き出したファイルの (根からの相対パス, 中身)
+///
+/// 取り込んだファイルがさらに取り込んでいるものも含む。抜き出したファイルの中身は、抜き出した部分だけにする。
+pub fn dependencies(block_elements: &[BlockElement]) -> Vec<(PathBuf, String)> {
+    let mut collector = Dependencies {
+        dependencies: vec![],
+        seen: HashSet::new(),
+    };
+    visit::walk_block_elements(
+        &mut collector,
+        block_elements,
+        Field::BlockElements,
+        &mut NodePath::default(),
+    );
+    collector.dependencies
+}
+
+struct Dependencies {
+    dependencies: Vec<(PathBuf, String)>,
+    seen: HashSet<PathBuf>,
+}
+
+impl Visit for Dependencies {
+    fn visit_block_element(&mut self, block_element: &BlockElement, path: &mut NodePath) {
+        match block_element {
+            BlockElement::Include(Include {
+                content: Some(content),
+                ..
+            }) if self.seen.insert(content.file.path.clone()) => {
+                self.dependencies
+                    .push((content.file.path.clone(), content.file.text.clone()));
+                visit::walk_block_elements(
+                    self,
+                    &content.file.block_elements,
+                    Field::BlockElements,
+                    &mut NodePath::default(),
+                );
+            }
+            // 抜き出せなかったものも、ファイルが直ったときに出力し直せるよう含める
+            BlockElement::Code {
+                excerpt:
+                    Some(Excerpt {
+                        source: Some(source),
+                        text,
+                        ..
+                    }),
+                ..
+            } => {
+                self.dependencies
+                    .push((source.clone(), text.clone().unwrap_or_default()));
+            }
+            _ => {}
+        }
+        visit::walk_block_element(self, block_element, path);
+    }
+}
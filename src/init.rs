@@ -0,0 +1,52 @@
+//! `bokuteki init` で作る雛形のプロジェクト
+
+use crate::config::CONFIG_FILE_NAME;
+use crate::print::Template;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const GITIGNORE: &str = "/output/\n";
+
+/// `dir` に雛形のプロジェクトを作り、作ったファイルを返す
+///
+/// 既存のファイルは上書きしない。1 つでもあれば何も作らずにエラーを返す。
+pub fn init(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let template = Template::embedded();
+    let files: [(&str, &str); 7] = [
+        (
+            CONFIG_FILE_NAME,
+            include_str!("../bokuteki-config/init/bokuteki.toml"),
+        ),
+        (
+            "index.bok",
+            include_str!("../bokuteki-config/init/index.bok"),
+        ),
+        (
+            "chapter.bok",
+            include_str!("../bokuteki-config/init/chapter.bok"),
+        ),
+        ("template/template.html", &template.html),
+        ("template/bokuteki.css", &template.css),
+        ("template/bokuteki.js", &template.js),
+        (".gitignore", GITIGNORE),
+    ];
+
+    let paths: Vec<_> = files.iter().map(|(path, _)| dir.join(path)).collect();
+    if let Some(existing) = paths.iter().find(|path| path.exists()) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!(
+                "refusing to overwrite existing file: {}",
+                existing.display()
+            ),
+        ));
+    }
+    for (path, (_, content)) in paths.iter().zip(files) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+    }
+    Ok(paths)
+}
@@ -1,27 +1,75 @@
+use serde::Deserialize;
 use std::env;
 use std::fs;
+use std::io;
+use std::path::Path;
 use std::process::Command;
 use uuid::Uuid;
 
-pub fn render(src: String, is_display_mode: bool) -> String {
+const RENDER_BATCH_JS: &str = include_str!("../bokuteki-config/katex/render-batch.js");
+
+/// `npx katex` で TeX を HTML にする。katex が失敗した場合もエラーを返す
+pub fn render(src: String, is_display_mode: bool) -> io::Result<String> {
+    render_batch(&[(src, is_display_mode)])?
+        .pop()
+        .unwrap_or_else(|| Err("katex returned no result".to_string()))
+        .map_err(io::Error::other)
+}
+
+/// `requests` の数式 (TeX, display mode か) を 1 つの katex のプロセスでまとめて HTML にする
+///
+/// 結果は `requests` と同じ順に並ぶ。katex を起動できなかった場合はエラーを返す
+pub fn render_batch(requests: &[(String, bool)]) -> io::Result<Vec<Result<String, String>>> {
+    if requests.is_empty() {
+        return Ok(vec![]);
+    }
     let mut path = env::temp_dir();
     path.push(Uuid::new_v4().to_string());
-    fs::create_dir(path.as_path()).expect("failed to create temporary dir");
-    path.push("input.txt");
-    let mut input_file = fs::File::create(path.as_path()).expect("failed to create file");
-    use std::io::prelude::*;
-    writeln!(input_file, "{}", src).expect("failed to write input src");
-    let input_filepath = path.clone();
-    path.pop();
+    fs::create_dir(path.as_path())?;
+    let output = render_in(&path, requests);
+    fs::remove_dir_all(path)?;
+    let output = output?;
 
-    let mut command = Command::new("npx");
-    command.arg("katex");
-    if is_display_mode {
-        command.arg("--display-mode");
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    let results: Vec<Rendered> = serde_json::from_slice(&output.stdout)?;
+    if results.len() != requests.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "katex returned a wrong number of results",
+        ));
     }
-    command.arg("--input").arg(input_filepath);
-    let output = command.output().expect("failed to execute `npx katex`");
-    fs::remove_dir_all(path).expect("failed to remove temporary file");
+    Ok(results
+        .into_iter()
+        .map(|rendered| match rendered {
+            Rendered::Ok(html) => Ok(html),
+            Rendered::Err(message) => Err(message),
+        })
+        .collect())
+}
+
+// render-batch.js が出力する 1 つの数式の結果
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Rendered {
+    Ok(String),
+    Err(String),
+}
+
+fn render_in(dir: &Path, requests: &[(String, bool)]) -> io::Result<std::process::Output> {
+    fs::write(dir.join("render-batch.js"), RENDER_BATCH_JS)?;
+    fs::write(dir.join("input.json"), serde_json::to_vec(requests)?)?;
 
-    String::from_utf8(output.stdout).expect("failed to recognize stdout as utf8 string")
+    Command::new("npx")
+        .args([
+            "--package",
+            "katex",
+            "-c",
+            "node render-batch.js input.json",
+        ])
+        .current_dir(dir)
+        .output()
 }
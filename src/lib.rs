@@ -0,0 +1,63 @@
+//! Bokuteki 文書のパーサ・HTML プリンタ・ビルダ
+//!
+//! ```no_run
+//! let parsed = bokuteki::parse("# Hello\n\n$x$ is a variable.");
+//! assert!(parsed.errors.is_empty());
+//! let html = bokuteki::render_html(
+//!     &parsed.source,
+//!     &parsed.document.block_elements,
+//!     &bokuteki::RenderOptions::default(),
+//! );
+//! println!("{}", html);
+//! ```
+
+pub mod build;
+mod cache;
+pub mod config;
+pub mod diagnostic;
+pub mod document;
+pub mod event;
+mod excerpt;
+mod include;
+pub mod init;
+mod katex;
+pub mod lint;
+mod output;
+pub mod parse;
+pub mod print;
+pub mod serve;
+mod util;
+pub mod visit;
+pub mod watch;
+
+pub use build::{
+    build, build_with_cache, check, common_root, expand_entries, render, BuildCache, BuildConfig,
+    BuildReport, FileReport,
+};
+pub use diagnostic::Diagnostic;
+pub use document::{Document, Source};
+pub use event::{Event, Parser, Tag};
+pub use parse::{Error, Warning};
+pub use print::{render_html, MathMode, PageOptions, RenderOptions, Template};
+
+/// `parse` の結果
+#[derive(Debug)]
+pub struct Parsed<'a> {
+    /// `document` 中の range が指すソース
+    pub source: Source<'a>,
+    pub document: Document,
+    pub errors: Vec<Error>,
+    pub warnings: Vec<Warning>,
+}
+
+/// 文字列を Bokuteki 文書としてパースする
+pub fn parse(text: &str) -> Parsed<'_> {
+    let source = Source::new(text);
+    let result = parse::parse_document(&source, source.block_range());
+    Parsed {
+        source,
+        document: result.value,
+        errors: result.errors,
+        warnings: result.warnings,
+    }
+}
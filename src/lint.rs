@@ -0,0 +1,353 @@
+//! パースできた文書に対する追加の検査 (数式の対応、リンク先の存在)
+
+use crate::build;
+use crate::diagnostic::Diagnostic;
+use crate::document::*;
+use crate::parse::{Error, Warning};
+use crate::print;
+use crate::visit::{self, Field, NodePath, Visit};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// 文書中の数式とリンクを検査する。`dir` は相対リンクの基準となるディレクトリ
+///
+/// 見つけたものはすべて警告にする。数式に誤りがあっても、KaTeX で描画できなければ
+/// ブラウザ側での描画に任せるので、ページは出力できる。
+pub fn lint_document(src: &Source, document: &Document, dir: &Path) -> Vec<Warning> {
+    let mut linter = Linter {
+        src,
+        dir,
+        warnings: vec![],
+    };
+    linter.visit_document(document);
+    linter.warnings
+}
+
+struct Linter<'a, 'b> {
+    src: &'b Source<'a>,
+    dir: &'b Path,
+    warnings: Vec<Warning>,
+}
+
+impl Visit for Linter<'_, '_> {
+    fn visit_block_element(&mut self, block_element: &BlockElement, path: &mut NodePath) {
+        if let BlockElement::Math { lines } = block_element {
+            let lines: Vec<_> = lines.iter().copied().collect();
+            self.warnings.append(&mut validate_math(self.src, &lines));
+        }
+        visit::walk_block_element(self, block_element, path);
+    }
+
+    fn visit_inline_element(&mut self, inline_element: &InlineElement, path: &mut NodePath) {
+        match inline_element {
+            InlineElement::Math { range } => {
+                self.warnings
+                    .append(&mut validate_math(self.src, &[*range]));
+            }
+            InlineElement::Link { url_range, .. } => {
+                self.warnings
+                    .extend(check_link(self.src, self.dir, url_range));
+            }
+            _ => {}
+        }
+        visit::walk_inline_element(self, inline_element, path);
+    }
+}
+
+// 数式の開き括弧・環境の種類と位置
+enum Open<'a> {
+    Brace,
+    Left,
+    Begin(&'a str),
+}
+
+/// TeX の `{}`、`\left`/`\right`、`\begin`/`\end` の対応を検査する
+///
+/// KaTeX を起動せずに分かる範囲の誤りだけを見つける。
+fn validate_math(src: &Source, lines: &[InlineRange]) -> Vec<Warning> {
+    let mut errors = vec![];
+    let mut stack: Vec<(Open, InlineRange)> = vec![];
+    for line in lines {
+        let text = src.slice(line);
+        let mut chars = text.char_indices().peekable();
+        while let Some((idx, c)) = chars.next() {
+            let at = |len: usize| InlineRange {
+                line: line.line,
+                start_column: line.start_column + idx,
+                end_column: line.start_column + idx + len,
+            };
+            match c {
+                '\\' => {
+                    // 制御綴 (英字の並び) もしくは 1 文字の制御記号
+                    let rest = &text[idx + 1..];
+                    let name_len = rest
+                        .find(|c: char| !c.is_ascii_alphabetic())
+                        .unwrap_or(rest.len());
+                    let name_len = if name_len == 0 {
+                        rest.chars().next().map_or(0, char::len_utf8)
+                    } else {
+                        name_len
+                    };
+                    let name = &rest[..name_len];
+                    while chars
+                        .peek()
+                        .is_some_and(|(next, _)| *next <= idx + name_len)
+                    {
+                        chars.next();
+                    }
+                    let range = at(name_len + 1);
+                    match name {
+                        "left" => stack.push((Open::Left, range)),
+                        "right" => match stack.pop() {
+                            Some((Open::Left, _)) => {}
+                            Some(open) => {
+                                errors.push(mismatched(&open, range, "\\right"));
+                            }
+                            None => errors.push(Diagnostic::new(
+                                "invalid-math",
+                                range,
+                                "unmatched '\\right' in math.",
+                            )),
+                        },
+                        "begin" | "end" => {
+                            let env = environment_name(&rest[name_len..]);
+                            let range = at(name_len + 1 + env.map_or(0, |env| env.len() + 2));
+                            if name == "begin" {
+                                stack.push((Open::Begin(env.unwrap_or("")), range));
+                            } else {
+                                match stack.pop() {
+                                    Some((Open::Begin(begin), _)) if Some(begin) == env => {}
+                                    Some(open) => {
+                                        errors.push(mismatched(&open, range, "\\end"));
+                                    }
+                                    None => errors.push(Diagnostic::new(
+                                        "invalid-math",
+                                        range,
+                                        "unmatched '\\end' in math.",
+                                    )),
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                '{' => stack.push((Open::Brace, at(1))),
+                '}' => match stack.pop() {
+                    Some((Open::Brace, _)) => {}
+                    Some(open) => errors.push(mismatched(&open, at(1), "}")),
+                    None => errors.push(Diagnostic::new(
+                        "invalid-math",
+                        at(1),
+                        "unmatched '}' in math.",
+                    )),
+                },
+                _ => {}
+            }
+        }
+    }
+    for (open, range) in stack {
+        errors.push(Diagnostic::new(
+            "invalid-math",
+            range,
+            format!("unclosed {} in math.", describe(&open)),
+        ));
+    }
+    errors
+}
+
+// `\begin{name}` の `{name}` 部分から name を得る
+fn environment_name(rest: &str) -> Option<&str> {
+    let rest = rest.strip_prefix('{')?;
+    rest.find('}').map(|end| &rest[..end])
+}
+
+fn describe(open: &Open) -> String {
+    match open {
+        Open::Brace => "'{'".to_string(),
+        Open::Left => "'\\left'".to_string(),
+        Open::Begin(env) => format!("'\\begin{{{}}}'", env),
+    }
+}
+
+fn mismatched(open: &(Open, InlineRange), range: InlineRange, close: &str) -> Warning {
+    Diagnostic::new(
+        "invalid-math",
+        range,
+        format!("'{}' does not match {} in math.", close, describe(&open.0)),
+    )
+    .with_label(open.1, "opened here")
+}
+
+// 相対リンクの指す先がソースの側に存在するかを検査する
+// 出力先の `.html` へのリンクは、対応する `.bok` があればよい
+fn check_link(src: &Source, dir: &Path, url_range: &InlineRange) -> Option<Warning> {
+    let url = src.slice(url_range);
+    if url.is_empty() {
+        return Some(Diagnostic::new(
+            "broken-link",
+            *url_range,
+            "link target is empty.",
+        ));
+    }
+    if url.starts_with('#') || url.starts_with('/') || url.contains(':') {
+        return None;
+    }
+    let target = url.split(['#', '?']).next().unwrap_or("");
+    // `.bok` へのリンクは、ビルドに含まれるかをビルドのときに検査する
+    if target.is_empty() || bok_link(url).is_some() {
+        return None;
+    }
+    let path = dir.join(target);
+    if path.exists()
+        || (path.extension().is_some_and(|ext| ext == "html")
+            && path.with_extension("bok").exists())
+    {
+        return None;
+    }
+    Some(Diagnostic::new(
+        "broken-link",
+        *url_range,
+        format!("link target not found: {}", target),
+    ))
+}
+
+/// `.bok` ファイルへの相対リンクなら、(パス, アンカー) に分ける
+pub fn bok_link(url: &str) -> Option<(&str, Option<&str>)> {
+    if url.starts_with('/') || url.contains(':') {
+        return None;
+    }
+    let (path, anchor) = match url.split_once('#') {
+        Some((path, anchor)) => (path, Some(anchor)),
+        None => (url, None),
+    };
+    path.ends_with(".bok").then_some((path, anchor))
+}
+
+/// `file_path` のファイルに書かれたリンクの `.bok` のパスを、根からの相対パスにする
+///
+/// 根の外を指すものは None。
+pub fn resolve_bok_link(file_path: &Path, path: &str) -> Option<PathBuf> {
+    build::normalize(&file_path.parent().unwrap_or(Path::new("")).join(path))
+}
+
+/// 別のページへのリンク
+#[derive(Debug, Clone)]
+pub struct PageLink {
+    /// 書かれたパス
+    pub path: String,
+    /// 根からの相対パス。根の外を指すなら None
+    pub target: Option<PathBuf>,
+    pub anchor: Option<String>,
+    pub range: InlineRange,
+}
+
+/// `src_path` のファイルの文書中の `.bok` へのリンクを集める。include したブロック要素の中は含まない
+pub fn page_links(src: &Source, src_path: &Path, block_elements: &[BlockElement]) -> Vec<PageLink> {
+    let mut collector = PageLinks {
+        src,
+        src_path,
+        links: vec![],
+    };
+    visit::walk_block_elements(
+        &mut collector,
+        block_elements,
+        Field::BlockElements,
+        &mut NodePath::default(),
+    );
+    collector.links
+}
+
+struct PageLinks<'a, 'b> {
+    src: &'b Source<'a>,
+    src_path: &'b Path,
+    links: Vec<PageLink>,
+}
+
+impl Visit for PageLinks<'_, '_> {
+    fn visit_inline_element(&mut self, inline_element: &InlineElement, path: &mut NodePath) {
+        if let InlineElement::Link { url_range, .. } = inline_element {
+            if let Some((link_path, anchor)) = bok_link(self.src.slice(url_range)) {
+                self.links.push(PageLink {
+                    path: link_path.to_string(),
+                    target: resolve_bok_link(self.src_path, link_path),
+                    anchor: anchor.map(str::to_string),
+                    range: *url_range,
+                });
+            }
+        }
+        visit::walk_inline_element(self, inline_element, path);
+    }
+}
+
+/// ページ中の見出しと定理の id を集める。include したブロック要素の中も含む
+pub fn anchors(src: &Source, block_elements: &[BlockElement]) -> HashSet<String> {
+    let mut collector = Anchors {
+        src,
+        anchors: HashSet::new(),
+    };
+    visit::walk_block_elements(
+        &mut collector,
+        block_elements,
+        Field::BlockElements,
+        &mut NodePath::default(),
+    );
+    collector.anchors
+}
+
+struct Anchors<'a, 'b> {
+    src: &'b Source<'a>,
+    anchors: HashSet<String>,
+}
+
+impl Visit for Anchors<'_, '_> {
+    fn visit_block_element(&mut self, block_element: &BlockElement, path: &mut NodePath) {
+        match block_element {
+            BlockElement::Heading { content: title, .. } | BlockElement::Theorem { title, .. } => {
+                self.anchors
+                    .extend(print::anchor_id(&self.src.plain_text(title)));
+            }
+            BlockElement::Include(Include {
+                content: Some(content),
+                ..
+            }) => {
+                let src = Source::new(&content.file.text);
+                self.anchors.extend(anchors(&src, content.block_elements()));
+            }
+            _ => {}
+        }
+        visit::walk_block_element(self, block_element, path);
+    }
+}
+
+/// リンク先のページがビルドに含まれ、アンカーがそのページにあるかを検査する
+///
+/// `pages` はビルドに含まれるページの根からの相対パスと、そのページの id。
+pub fn check_page_links(
+    links: &[PageLink],
+    pages: &HashMap<PathBuf, HashSet<String>>,
+) -> Vec<Error> {
+    links
+        .iter()
+        .filter_map(|link| {
+            let Some(anchors) = link.target.as_ref().and_then(|target| pages.get(target)) else {
+                return Some(Diagnostic::new(
+                    "unknown-link-target",
+                    link.range,
+                    format!("link target '{}' is not part of the build.", link.path),
+                ));
+            };
+            let anchor = link.anchor.as_ref()?;
+            if anchors.contains(anchor) {
+                return None;
+            }
+            Some(Diagnostic::new(
+                "unknown-link-anchor",
+                link.range,
+                format!(
+                    "'{}' has no heading or theorem with id '{}'.",
+                    link.path, anchor
+                ),
+            ))
+        })
+        .collect()
+}
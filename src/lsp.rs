@@ -12,15 +12,19 @@ use lsp_types::{
 pub async fn run() {
     let (connection, io_threads) = Connection::stdio();
 
-    let mut text_document_sync_options = TextDocumentSyncOptions::default();
-    text_document_sync_options.open_close = Some(true);
-    text_document_sync_options.change = Some(TextDocumentSyncKind::FULL);
-    text_document_sync_options.save = Some(TextDocumentSyncSaveOptions::Supported(true));
+    let text_document_sync_options = TextDocumentSyncOptions {
+        open_close: Some(true),
+        change: Some(TextDocumentSyncKind::FULL),
+        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+        ..Default::default()
+    };
 
-    let mut server_capabilities = ServerCapabilities::default();
-    server_capabilities.text_document_sync = Some(TextDocumentSyncCapability::Options(
-        text_document_sync_options,
-    ));
+    let server_capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            text_document_sync_options,
+        )),
+        ..Default::default()
+    };
     let server_capabilities = serde_json::to_value(server_capabilities)
         .expect("failed to convert server capabilities to json value..");
 
@@ -100,7 +104,7 @@ fn handle_did_change_text_document_notification(
     let src = did_change_text_document
         .content_changes
         .into_iter()
-        .nth(0)
+        .next()
         .unwrap()
         .text;
     let (errors, warnings) = check_document::check_bok_content(src);
@@ -1,12 +1,11 @@
-mod build;
-mod document;
-mod katex;
 mod lsp;
-mod parse;
-mod print;
-mod util;
+mod message_format;
 
+use bokuteki::config::{self, ProjectConfig};
 use clap::{Parser, Subcommand};
+use message_format::MessageFormat;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 struct AppArgs {
@@ -14,35 +13,497 @@ struct AppArgs {
     action: Action,
 }
 
+// `build` の終了コード。複数当てはまる場合は値の大きいものを返す
+// 起点のファイルが読めない場合は、import 先が見つからない場合と区別できるよう EXIT_IO_ERROR にする
+const EXIT_PARSE_ERROR: i32 = 1;
+const EXIT_MISSING_IMPORT: i32 = 2;
+const EXIT_IO_ERROR: i32 = 3;
+
 #[derive(Subcommand)]
 enum Action {
-    Build {
+    /// Build HTML pages from an entry file and the files it imports
+    ///
+    /// Exit status is 0 on success, 1 if a file has parse errors, 2 if an imported file is
+    /// missing and 3 on I/O failure, including an entry file that cannot be read. If several
+    /// apply, the largest one is returned.
+    ///
+    /// Settings are read from `bokuteki.toml` found in the entry file's directory or its
+    /// ancestors (the current directory when no entry is given). Flags override them.
+    Build(BuildArgs),
+    /// Build, then rebuild whenever an imported file or the template changes
+    ///
+    /// Only the changed files are parsed and rendered again. A template change re-renders
+    /// every page.
+    Watch(BuildArgs),
+    /// Watch like `watch` and serve the output directory on a local port with live reload
+    Serve {
+        #[clap(flatten)]
+        build_args: BuildArgs,
+        /// Port to listen on (127.0.0.1 only)
+        #[clap(long, default_value = "8000")]
+        port: u16,
+    },
+    /// Parse an entry file and the files it imports, and report diagnostics without writing output
+    ///
+    /// Exit status is the same as `build`.
+    Check {
+        /// Entry files or glob patterns. Defaults to `entry` in bokuteki.toml
+        filepaths: Vec<String>,
+        /// Directory to look for imported files in, after the importing file's directory.
+        /// Can be given several times. Added after `search-paths` in bokuteki.toml
+        #[clap(long = "search-path")]
+        search_paths: Vec<String>,
+        /// Treat warnings as errors
+        #[clap(long)]
+        deny_warnings: bool,
+        /// Output format of diagnostics. `json` and `sarif` are written to stdout
+        #[clap(long, arg_enum, default_value = "human")]
+        message_format: MessageFormat,
+    },
+    /// Render a single document to stdout without following imports or writing files
+    ///
+    /// Diagnostics go to stderr. Exit status is 1 if the document has errors, and the
+    /// HTML is written anyway with the failed parts shown in place.
+    Render {
+        /// Document to render, or `-` to read from stdin
+        #[clap(default_value = "-")]
         filepath: String,
-        #[clap(short = 'o', long = "output")]
-        output_path: Option<String>,
+        /// Write a full page using the template instead of an HTML fragment.
+        /// The page links bokuteki.css and bokuteki.js next to it
+        #[clap(long)]
+        page: bool,
+        #[clap(flatten)]
+        page_args: PageArgs,
+        /// Treat warnings as errors
+        #[clap(long)]
+        deny_warnings: bool,
+        /// Output format of diagnostics. `json` and `sarif` are written to stderr
+        #[clap(long, arg_enum, default_value = "human")]
+        message_format: MessageFormat,
+    },
+    /// Create a starter project with bokuteki.toml, sample pages and a copy of the default template
+    Init {
+        /// Directory to create the project in
+        #[clap(default_value = ".")]
+        dir: String,
     },
     Lsp,
 }
 
+/// `build` と `watch` のオプション
+#[derive(clap::Args)]
+struct BuildArgs {
+    /// Entry files or glob patterns such as 'lectures/*.bok'. Defaults to `entry` in
+    /// bokuteki.toml. Files are output relative to the directory of bokuteki.toml, or
+    /// without one, to the deepest directory containing all of them
+    filepaths: Vec<String>,
+    #[clap(short = 'o', long = "output")]
+    output_path: Option<String>,
+    /// Directory to look for imported files in, after the importing file's directory.
+    /// Can be given several times. Added after `search-paths` in bokuteki.toml
+    #[clap(long = "search-path")]
+    search_paths: Vec<String>,
+    #[clap(flatten)]
+    page_args: PageArgs,
+    /// Render pages even if they have parse errors, showing the failed parts in place
+    #[clap(long)]
+    keep_going: bool,
+    /// Treat warnings as errors
+    #[clap(long)]
+    deny_warnings: bool,
+    /// Write into the output directory even if it is not empty and was not created by bokuteki
+    #[clap(long)]
+    force: bool,
+    /// Output format of diagnostics. `json` and `sarif` are written to stdout
+    #[clap(long, arg_enum, default_value = "human")]
+    message_format: MessageFormat,
+}
+
+/// ページの出力に関するオプション。指定しなければ bokuteki.toml の値を使う
+#[derive(clap::Args)]
+struct PageArgs {
+    /// Directory containing template.html, bokuteki.css and bokuteki.js
+    #[clap(long)]
+    template: Option<String>,
+    /// How math is rendered: `katex` (at build time) or `client` (in the browser)
+    #[clap(long)]
+    math_mode: Option<bokuteki::MathMode>,
+    /// Color theme of the pages (`light` or `dark`)
+    #[clap(long)]
+    theme: Option<String>,
+    /// Language of the pages, set to `<html lang>`
+    #[clap(long)]
+    lang: Option<String>,
+    /// URL where the pages are published, used for canonical links
+    #[clap(long)]
+    base_url: Option<String>,
+}
+
 #[tokio::main]
 async fn main() {
     init();
 
     match AppArgs::parse().action {
-        Action::Build {
+        Action::Build(args) => {
+            let message_format = args.message_format;
+            let config = build_config(args);
+            let root = config.root.clone();
+            let exit_code = match bokuteki::build(&config) {
+                Ok(report) => {
+                    message_format::print_build_report(&report, &root, message_format);
+                    build_exit_code(&report)
+                }
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    EXIT_IO_ERROR
+                }
+            };
+            std::process::exit(exit_code);
+        }
+        Action::Watch(args) => {
+            let message_format = args.message_format;
+            let config = build_config(args);
+            let root = config.root.clone();
+            bokuteki::watch::watch(&config, |result| {
+                match result {
+                    Ok(report) => message_format::print_build_report(report, &root, message_format),
+                    Err(err) => eprintln!("error: {}", err),
+                }
+                eprintln!("watching for changes...");
+            });
+        }
+        Action::Serve { build_args, port } => {
+            let message_format = build_args.message_format;
+            let config = build_config(build_args);
+            let root = config.root.clone();
+            let listener = match tokio::net::TcpListener::bind(("127.0.0.1", port)).await {
+                Ok(listener) => listener,
+                Err(err) => {
+                    eprintln!("error: cannot listen on port {}: {}", port, err);
+                    std::process::exit(EXIT_IO_ERROR);
+                }
+            };
+            let entry_page = config.entries[0]
+                .strip_prefix(&config.root)
+                .unwrap_or(Path::new(""))
+                .with_extension("html");
+            let entry_url: Vec<_> = entry_page
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect();
+            eprintln!(
+                "serving on http://127.0.0.1:{}/{}",
+                port,
+                entry_url.join("/")
+            );
+            let result = bokuteki::serve::serve(config, listener, move |result| {
+                match result {
+                    Ok(report) => message_format::print_build_report(report, &root, message_format),
+                    Err(err) => eprintln!("error: {}", err),
+                }
+                eprintln!("watching for changes...");
+            })
+            .await;
+            if let Err(err) = result {
+                eprintln!("error: {}", err);
+                std::process::exit(EXIT_IO_ERROR);
+            }
+        }
+        Action::Render {
             filepath,
-            output_path,
+            page,
+            page_args,
+            deny_warnings,
+            message_format,
         } => {
-            use std::path::PathBuf;
-            build::build(
-                PathBuf::from(filepath),
-                output_path.map_or_else(|| PathBuf::from("./output"), |str| PathBuf::from(str)),
+            let from_stdin = filepath == "-";
+            let project =
+                load_project((!from_stdin).then_some(filepath.as_str())).unwrap_or_default();
+            let (content, path) = if from_stdin {
+                let mut content = String::new();
+                let result = std::io::Read::read_to_string(&mut std::io::stdin(), &mut content);
+                (result.map(|_| content), PathBuf::from("<stdin>"))
+            } else {
+                (std::fs::read_to_string(&filepath), PathBuf::from(&filepath))
+            };
+            let content = match content {
+                Ok(content) => content,
+                Err(err) => {
+                    eprintln!("error: cannot read {}: {}", path.display(), err);
+                    std::process::exit(EXIT_IO_ERROR);
+                }
+            };
+            let template = match template_dir(page_args.template, &project) {
+                Some(template_dir) if page => match bokuteki::Template::load(&template_dir) {
+                    Ok(template) => template,
+                    Err(err) => {
+                        eprintln!("error: {}", err);
+                        std::process::exit(EXIT_IO_ERROR);
+                    }
+                },
+                _ => bokuteki::Template::embedded(),
+            };
+            let render_options = bokuteki::RenderOptions {
+                math_mode: page_args.math_mode.or(project.math).unwrap_or_default(),
+            };
+            let page_options =
+                page_options(page_args.theme, page_args.lang, page_args.base_url, project);
+            let (html, mut file_report) = bokuteki::render(
+                &content,
+                &path,
+                &render_options,
+                page.then_some((&template, &page_options)),
             );
+            if let Err(err) = write_stdout(&html) {
+                // `| head` などで読み手が先に閉じたなら、それ以上は書かずに続ける
+                if err.kind() != std::io::ErrorKind::BrokenPipe {
+                    eprintln!("error: cannot write to stdout: {}", err);
+                    std::process::exit(EXIT_IO_ERROR);
+                }
+            }
+            if deny_warnings {
+                file_report.errors.append(&mut file_report.warnings);
+            }
+            let report = bokuteki::BuildReport {
+                files: vec![file_report],
+                ..bokuteki::BuildReport::default()
+            };
+            // 標準出力は HTML に使うので、診断は形式によらず標準エラー出力に出す
+            message_format::eprint_build_report(&report, Path::new(""), message_format);
+            std::process::exit(build_exit_code(&report));
+        }
+        Action::Check {
+            filepaths,
+            search_paths,
+            deny_warnings,
+            message_format,
+        } => {
+            let inputs = resolve_inputs(filepaths, search_paths);
+            let exit_code = match bokuteki::check(
+                &inputs.entries,
+                &inputs.root,
+                &inputs.search_paths,
+                deny_warnings,
+            ) {
+                Ok(report) => {
+                    message_format::print_build_report(&report, &inputs.root, message_format);
+                    build_exit_code(&report)
+                }
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    EXIT_IO_ERROR
+                }
+            };
+            std::process::exit(exit_code);
         }
+        Action::Init { dir } => match bokuteki::init::init(Path::new(&dir)) {
+            Ok(paths) => {
+                for path in paths {
+                    eprintln!("created {}", path.display());
+                }
+            }
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(EXIT_IO_ERROR);
+            }
+        },
         Action::Lsp => lsp::run().await,
     }
 }
 
+// コマンドラインと bokuteki.toml からビルドの設定を決める
+fn build_config(args: BuildArgs) -> bokuteki::BuildConfig {
+    let Inputs {
+        entries,
+        root,
+        search_paths,
+        project,
+    } = resolve_inputs(args.filepaths, args.search_paths);
+    let output_dir = match (args.output_path, &project) {
+        (Some(output_path), _) => PathBuf::from(output_path),
+        (None, Some(project)) => project
+            .output
+            .clone()
+            .unwrap_or_else(|| project.dir.join("output")),
+        (None, None) => PathBuf::from("./output"),
+    };
+    let project = project.unwrap_or_default();
+    let page_args = args.page_args;
+    bokuteki::BuildConfig {
+        entries,
+        root,
+        output_dir,
+        template_dir: template_dir(page_args.template, &project),
+        search_paths,
+        render_options: bokuteki::RenderOptions {
+            math_mode: page_args.math_mode.or(project.math).unwrap_or_default(),
+        },
+        page_options: page_options(page_args.theme, page_args.lang, page_args.base_url, project),
+        keep_going: args.keep_going,
+        deny_warnings: args.deny_warnings,
+        force: args.force,
+    }
+}
+
+// コマンドラインと bokuteki.toml から決まる、ビルドの入力
+struct Inputs {
+    entries: Vec<PathBuf>,
+    root: PathBuf,
+    /// `root` からの相対パス
+    search_paths: Vec<PathBuf>,
+    project: Option<ProjectConfig>,
+}
+
+// 起点のファイル、プロジェクトの根、import 先を探すディレクトリ、bokuteki.toml を決める。
+// 決められなければ終了する
+//
+// bokuteki.toml があり、起点のファイルがすべてその下にあれば、そのディレクトリを根にする。
+fn resolve_inputs(filepaths: Vec<String>, search_paths: Vec<String>) -> Inputs {
+    let project = load_project(filepaths.first().map(String::as_str));
+    let patterns: Vec<PathBuf> = if !filepaths.is_empty() {
+        filepaths.iter().map(PathBuf::from).collect()
+    } else {
+        match &project {
+            Some(project) if !project.entries.is_empty() => project.entries.clone(),
+            _ => {
+                eprintln!(
+                    "error: no entry file given and no `entry` in {}",
+                    config::CONFIG_FILE_NAME
+                );
+                std::process::exit(EXIT_IO_ERROR);
+            }
+        }
+    };
+    let mut entries = match bokuteki::expand_entries(&patterns) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(EXIT_IO_ERROR);
+        }
+    };
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let project_dir = project
+        .as_ref()
+        .map(|project| &project.dir)
+        .filter(|dir| entries.iter().all(|entry| cwd.join(entry).starts_with(dir)));
+    // 絶対パスと相対パスが混ざっていれば、共通の根を求められるようすべて絶対パスにする
+    if project_dir.is_some() || entries.iter().any(|entry| entry.is_absolute()) {
+        entries = entries.iter().map(|entry| cwd.join(entry)).collect();
+    }
+    let root = match project_dir {
+        Some(dir) => dir.clone(),
+        None => bokuteki::common_root(&entries),
+    };
+
+    // '..' を含んでいても根の外かどうかを判定できるよう、実際のパスで比べる
+    let canonical_root = std::fs::canonicalize(&root).unwrap_or_else(|_| cwd.join(&root));
+    let mut search_paths: Vec<PathBuf> = project
+        .iter()
+        .flat_map(|project| project.search_paths.iter().cloned())
+        .chain(search_paths.iter().map(PathBuf::from))
+        .map(|search_path| {
+            let canonical = match std::fs::canonicalize(&search_path) {
+                Ok(canonical) => canonical,
+                Err(err) => {
+                    eprintln!("error: search path {}: {}", search_path.display(), err);
+                    std::process::exit(EXIT_IO_ERROR);
+                }
+            };
+            match canonical.strip_prefix(&canonical_root) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => {
+                    eprintln!(
+                        "error: search path {} is outside the project root {}",
+                        search_path.display(),
+                        root.display()
+                    );
+                    std::process::exit(EXIT_IO_ERROR);
+                }
+            }
+        })
+        .collect();
+    // bokuteki.toml とコマンドラインで同じディレクトリが指定されても 1 度だけ探す
+    let mut seen = std::collections::HashSet::new();
+    search_paths.retain(|search_path| seen.insert(search_path.clone()));
+    Inputs {
+        entries,
+        root,
+        search_paths,
+        project,
+    }
+}
+
+// `filepath` のあるディレクトリ (なければカレントディレクトリ) から bokuteki.toml を探して読む
+fn load_project(filepath: Option<&str>) -> Option<ProjectConfig> {
+    let search_start = match filepath {
+        Some(filepath) => Path::new(filepath)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .to_path_buf(),
+        None => PathBuf::from(""),
+    };
+    // 相対パスの ancestors は途中で尽きるので、絶対パスにしてから探す
+    let search_start = std::env::current_dir()
+        .map(|cwd| cwd.join(&search_start))
+        .unwrap_or(search_start);
+    match config::discover(&search_start).map(|path| config::load(&path)) {
+        Some(Ok(project)) => Some(project),
+        Some(Err(err)) => {
+            eprintln!("error: {}", err);
+            std::process::exit(EXIT_IO_ERROR);
+        }
+        None => None,
+    }
+}
+
+// テンプレートは、オプション、bokuteki.toml、環境変数 `$BOKUTEKI_CONFIG_PATH` の順に探し、
+// どれもなければ埋め込みのものを使う
+fn template_dir(template: Option<String>, project: &ProjectConfig) -> Option<PathBuf> {
+    template
+        .map(PathBuf::from)
+        .or_else(|| project.template.clone())
+        .or_else(|| {
+            std::env::var_os("BOKUTEKI_CONFIG_PATH")
+                .map(|path| PathBuf::from(path).join("template"))
+        })
+}
+
+fn page_options(
+    theme: Option<String>,
+    language: Option<String>,
+    base_url: Option<String>,
+    project: ProjectConfig,
+) -> bokuteki::PageOptions {
+    let default = bokuteki::PageOptions::default();
+    bokuteki::PageOptions {
+        theme: theme.or(project.theme).unwrap_or(default.theme),
+        language: language.or(project.language).unwrap_or(default.language),
+        base_url: base_url.or(project.base_url),
+    }
+}
+
+// 末尾に改行がなければ足して標準出力に書く。`print!` と違い、書けなくてもパニックしない
+fn write_stdout(text: &str) -> std::io::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(text.as_bytes())?;
+    if !text.ends_with('\n') {
+        stdout.write_all(b"\n")?;
+    }
+    stdout.flush()
+}
+
+fn build_exit_code(report: &bokuteki::BuildReport) -> i32 {
+    if !report.missing_files.is_empty() {
+        EXIT_IO_ERROR
+    } else if report.has_missing_imports() {
+        EXIT_MISSING_IMPORT
+    } else if report.has_errors() {
+        EXIT_PARSE_ERROR
+    } else {
+        0
+    }
+}
+
 fn init() {
     // initialize logger
     simplelog::CombinedLogger::init(vec![simplelog::WriteLogger::new(
@@ -0,0 +1,259 @@
+use bokuteki::diagnostic::{Diagnostic, Severity};
+use bokuteki::document::InlineRange;
+use bokuteki::{BuildReport, Source};
+use serde_json::{json, Value};
+use std::path::{Component, Path};
+
+/// 診断の出力形式
+#[derive(clap::ArgEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// rustc 風の人間向けの表示 (標準エラー出力)
+    Human,
+    /// 1 行に 1 つの診断を JSON で出力 (標準出力)
+    Json,
+    /// SARIF 2.1.0 のログを 1 つ出力 (標準出力)
+    Sarif,
+}
+
+// 読めなかった起点のファイルの診断の識別子
+const MISSING_ENTRY_CODE: &str = "missing-entry";
+
+// SARIF で、プロジェクトの根を表す uriBaseId
+const SARIF_ROOT_ID: &str = "PROJECTROOT";
+
+/// ビルド結果の診断を出力する。`root` は `report` 中のパスの基準となるディレクトリ
+pub fn print_build_report(report: &BuildReport, root: &Path, format: MessageFormat) {
+    match format {
+        MessageFormat::Human => print_human(report),
+        MessageFormat::Json => {
+            for diagnostic in json_diagnostics(report, root) {
+                println!("{}", diagnostic);
+            }
+        }
+        MessageFormat::Sarif => println!("{}", sarif_log(report, root)),
+    }
+}
+
+/// `print_build_report` と同じだが、どの形式でも標準エラー出力に出す
+pub fn eprint_build_report(report: &BuildReport, root: &Path, format: MessageFormat) {
+    match format {
+        MessageFormat::Human => print_human(report),
+        MessageFormat::Json => {
+            for diagnostic in json_diagnostics(report, root) {
+                eprintln!("{}", diagnostic);
+            }
+        }
+        MessageFormat::Sarif => eprintln!("{}", sarif_log(report, root)),
+    }
+}
+
+fn print_human(report: &BuildReport) {
+    use std::io::IsTerminal;
+
+    let color = std::io::stderr().is_terminal();
+    for file in &report.files {
+        let src = Source::new(&file.content);
+        for (diagnostic, severity) in diagnostics_of(file) {
+            eprintln!("{}", diagnostic.render(&src, &file.path, severity, color));
+        }
+    }
+    for missing_file in &report.missing_files {
+        eprintln!("error: input file not found: {}", missing_file.display());
+    }
+}
+
+fn diagnostics_of(file: &bokuteki::FileReport) -> impl Iterator<Item = (&Diagnostic, Severity)> {
+    file.errors
+        .iter()
+        .map(|error| (error, Severity::Error))
+        .chain(
+            file.warnings
+                .iter()
+                .map(|warning| (warning, Severity::Warning)),
+        )
+}
+
+// 行・列は 1 始まり。`column` は文字単位、`utf16_column` は UTF-16 のコードユニット単位
+fn json_position(src: &Source, line: usize, column: usize) -> Value {
+    json!({
+        "line": line + 1,
+        "column": src.char_column(line, column) + 1,
+        "utf16_column": src.utf16_column(line, column) + 1,
+    })
+}
+
+fn json_range(src: &Source, range: &InlineRange) -> Value {
+    json!({
+        "start": json_position(src, range.line, range.start_column),
+        "end": json_position(src, range.line, range.end_column),
+    })
+}
+
+fn json_diagnostics(report: &BuildReport, root: &Path) -> Vec<Value> {
+    let mut diagnostics = vec![];
+    for file in &report.files {
+        let src = Source::new(&file.content);
+        let path = root.join(&file.path);
+        for (diagnostic, severity) in diagnostics_of(file) {
+            let labels: Vec<_> = diagnostic
+                .labels
+                .iter()
+                .map(|label| {
+                    json!({
+                        "message": label.message,
+                        "range": json_range(&src, &label.range),
+                    })
+                })
+                .collect();
+            diagnostics.push(json!({
+                "file": path.display().to_string(),
+                "severity": severity.as_str(),
+                "code": diagnostic.code,
+                "message": diagnostic.message,
+                "range": json_range(&src, &diagnostic.range),
+                "labels": labels,
+                "notes": diagnostic.notes,
+            }));
+        }
+    }
+    for missing_file in &report.missing_files {
+        diagnostics.push(json!({
+            "file": root.join(missing_file).display().to_string(),
+            "severity": Severity::Error.as_str(),
+            "code": MISSING_ENTRY_CODE,
+            "message": format!("input file not found: {}", missing_file.display()),
+            "range": Value::Null,
+            "labels": [],
+            "notes": [],
+        }));
+    }
+    diagnostics
+}
+
+// SARIF の位置は 1 始まりで、列は UTF-16 のコードユニット単位 (SARIF の既定)
+fn sarif_region(src: &Source, range: &InlineRange) -> Value {
+    json!({
+        "startLine": range.line + 1,
+        "startColumn": src.utf16_column(range.line, range.start_column) + 1,
+        "endLine": range.line + 1,
+        "endColumn": src.utf16_column(range.line, range.end_column) + 1,
+    })
+}
+
+// 根からの相対パスを SARIF の artifactLocation にする
+fn sarif_location(path: &Path) -> Value {
+    json!({ "uri": uri_path(path), "uriBaseId": SARIF_ROOT_ID })
+}
+
+// 根のディレクトリの file URI。末尾は '/' にする
+fn root_uri(root: &Path) -> String {
+    let root = std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+    let path = uri_path(&root);
+    if path.is_empty() {
+        "file:///".to_string()
+    } else {
+        format!("file:///{}/", path)
+    }
+}
+
+// パスを '/' 区切りにし、URI で使えない文字をパーセントエンコードする。先頭の '/' は付けない
+fn uri_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::RootDir | Component::CurDir => None,
+            // Windows のドライブ名 (`C:`) はそのまま
+            Component::Prefix(prefix) => Some(prefix.as_os_str().to_string_lossy().into_owned()),
+            Component::ParentDir => Some("..".to_string()),
+            Component::Normal(name) => Some(percent_encode(&name.to_string_lossy())),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded += &format!("%{:02X}", byte);
+        }
+    }
+    encoded
+}
+
+fn sarif_log(report: &BuildReport, root: &Path) -> Value {
+    let mut results = vec![];
+    let mut rule_ids = vec![];
+    for file in &report.files {
+        let src = Source::new(&file.content);
+        let location = sarif_location(&file.path);
+        for (diagnostic, severity) in diagnostics_of(file) {
+            let related_locations: Vec<_> = diagnostic
+                .labels
+                .iter()
+                .map(|label| {
+                    json!({
+                        "message": { "text": label.message },
+                        "physicalLocation": {
+                            "artifactLocation": location,
+                            "region": sarif_region(&src, &label.range),
+                        },
+                    })
+                })
+                .collect();
+            let mut text = diagnostic.message.clone();
+            for note in &diagnostic.notes {
+                text += &format!("\nnote: {}", note);
+            }
+            results.push(json!({
+                "ruleId": diagnostic.code,
+                "level": severity.as_str(),
+                "message": { "text": text },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": location,
+                        "region": sarif_region(&src, &diagnostic.range),
+                    },
+                }],
+                "relatedLocations": related_locations,
+            }));
+            rule_ids.push(diagnostic.code);
+        }
+    }
+    for missing_file in &report.missing_files {
+        results.push(json!({
+            "ruleId": MISSING_ENTRY_CODE,
+            "level": Severity::Error.as_str(),
+            "message": { "text": format!("input file not found: {}", missing_file.display()) },
+            "locations": [{
+                "physicalLocation": {
+                    "artifactLocation": sarif_location(missing_file),
+                },
+            }],
+        }));
+        rule_ids.push(MISSING_ENTRY_CODE);
+    }
+    rule_ids.sort_unstable();
+    rule_ids.dedup();
+    let rules: Vec<_> = rule_ids.iter().map(|id| json!({ "id": id })).collect();
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            // 位置は根からの相対パスで表し、根の場所はここにまとめる
+            "originalUriBaseIds": {
+                SARIF_ROOT_ID: { "uri": root_uri(root) },
+            },
+            "results": results,
+        }],
+    })
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// bokuteki が所有する出力ディレクトリに置く目印。前回のビルドで出力したファイルを 1 行に 1 つ並べる
+const MANIFEST_FILE_NAME: &str = ".bokuteki-output";
+const MANIFEST_HEADER: &str = "# files written by bokuteki. do not edit.";
+
+/// 出力ディレクトリ
+///
+/// 前回のビルドで出力したファイルを目印のファイルに記録しておき、
+/// 今回出力しなかったものだけを `finish` で削除する。それ以外のファイルには触れない。
+pub struct OutputDir {
+    path: PathBuf,
+    previous_files: BTreeSet<PathBuf>,
+    // 今回のビルドで出力した (もしくは残すことにした) ファイル。`path` からの相対パス
+    files: Mutex<BTreeSet<PathBuf>>,
+}
+
+impl OutputDir {
+    /// 出力ディレクトリを開く。なければ作る
+    ///
+    /// 目印のない空でないディレクトリは、`force` が true でなければ拒否する。
+    pub fn open(path: PathBuf, force: bool) -> io::Result<OutputDir> {
+        let manifest_path = path.join(MANIFEST_FILE_NAME);
+        let previous_files = if manifest_path.is_file() {
+            read_manifest(&manifest_path)?
+        } else if path.exists() {
+            if !path.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("output path is not a directory: {}", path.display()),
+                ));
+            }
+            if fs::read_dir(&path)?.next().is_some() && !force {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!(
+                        "refusing to write into non-empty directory not created by bokuteki: {} (use --force to write into it anyway)",
+                        path.display()
+                    ),
+                ));
+            }
+            BTreeSet::new()
+        } else {
+            fs::create_dir_all(&path)?;
+            BTreeSet::new()
+        };
+
+        Ok(OutputDir {
+            path,
+            previous_files,
+            files: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    pub fn write(&self, relative_path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        let path = self.prepare(relative_path)?;
+        fs::write(path, contents)
+    }
+
+    /// 前回までに出力したファイルが残っているか
+    pub fn contains(&self, relative_path: &Path) -> bool {
+        self.previous_files.contains(relative_path) && self.path.join(relative_path).is_file()
+    }
+
+    /// 今回は出力しないが、前回出力したものを残しておくファイル
+    pub fn keep(&self, relative_path: &Path) {
+        self.files
+            .lock()
+            .unwrap()
+            .insert(relative_path.to_path_buf());
+    }
+
+    /// 前回出力して今回出力しなかったファイルを削除し、目印を書き直す
+    pub fn finish(self) -> io::Result<()> {
+        let files = self.files.into_inner().unwrap();
+        for stale in self.previous_files.difference(&files) {
+            let path = self.path.join(stale);
+            if path.is_file() {
+                fs::remove_file(&path)?;
+            }
+            // 空になったディレクトリも片付ける
+            let mut dir = path.parent();
+            while let Some(parent) = dir {
+                if parent == self.path || fs::remove_dir(parent).is_err() {
+                    break;
+                }
+                dir = parent.parent();
+            }
+        }
+        write_manifest(&self.path.join(MANIFEST_FILE_NAME), &files)
+    }
+
+    fn prepare(&self, relative_path: &Path) -> io::Result<PathBuf> {
+        self.keep(relative_path);
+        let path = self.path.join(relative_path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(path)
+    }
+}
+
+fn read_manifest(path: &Path) -> io::Result<BTreeSet<PathBuf>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        // 出力ディレクトリの外を指すものは無視する
+        .filter(|line| {
+            Path::new(line)
+                .components()
+                .all(|component| matches!(component, std::path::Component::Normal(_)))
+        })
+        .map(PathBuf::from)
+        .collect())
+}
+
+fn write_manifest(path: &Path, files: &BTreeSet<PathBuf>) -> io::Result<()> {
+    let mut content = format!("{}\n", MANIFEST_HEADER);
+    for file in files {
+        let components: Vec<_> = file
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        content += &format!("{}\n", components.join("/"));
+    }
+    fs::write(path, content)
+}
@@ -1,10 +1,10 @@
+use crate::diagnostic::Diagnostic;
 use crate::document::*;
 use std::collections::HashMap;
-use std::collections::VecDeque;
 use std::path::PathBuf;
 
-pub type Error = String; // TODO: マシなエラー型をつける
-pub type Warning = String; // TODO: マシな警告型をつける
+pub type Error = Diagnostic;
+pub type Warning = Diagnostic;
 
 pub struct ParseResult<V, R> {
     pub value: V,
@@ -16,10 +16,7 @@ pub struct ParseResult<V, R> {
 type ParseBlockElementResult = ParseResult<BlockElement, BlockRange>;
 type ParseInlineElementResult = ParseResult<InlineElement, InlineRange>;
 
-pub fn parse_document(
-    src: &Vec<Vec<char>>,
-    src_range: BlockRange,
-) -> ParseResult<Document, BlockRange> {
+pub fn parse_document(src: &Source, src_range: BlockRange) -> ParseResult<Document, BlockRange> {
     let mut rest_range = src_range;
     let mut preamble = HashMap::new();
     let mut imports = vec![];
@@ -29,7 +26,7 @@ pub fn parse_document(
     // 空行は無視する
     loop {
         if let Some(top_line_range) = rest_range.front() {
-            if starts_with(src, "%", *top_line_range) {
+            if is_preamble_line(src, *top_line_range) {
                 let mut preamble_result = parse_preamble(src, *top_line_range);
                 let v = preamble_result.value;
                 preamble.insert(v.0, v.1);
@@ -56,9 +53,15 @@ pub fn parse_document(
     // 空行は無視する
     loop {
         if let Some(top_line_range) = rest_range.front() {
-            if starts_with(src, IMPORT_KEYWORD, top_line_range.clone()) {
-                let mut import_result = parse_import(src, top_line_range.clone());
-                imports.push(import_result.value);
+            if is_import_line(src, *top_line_range) {
+                let mut import_result = parse_import(src, *top_line_range);
+                // パースに失敗したパスは辿らない
+                if import_result.errors.is_empty() {
+                    imports.push(Import {
+                        path: import_result.value,
+                        range: *top_line_range,
+                    });
+                }
                 errors.append(&mut import_result.errors);
                 warnings.append(&mut import_result.warnings);
                 rest_range.pop_front();
@@ -95,32 +98,48 @@ pub fn parse_document(
     }
 }
 
-fn parse_preamble(
-    src: &Vec<Vec<char>>,
+pub(crate) fn is_preamble_line(src: &Source, line: InlineRange) -> bool {
+    starts_with(src, "%", line)
+}
+
+pub(crate) fn parse_preamble(
+    src: &Source,
     inline_range: InlineRange,
 ) -> ParseResult<(String, String), InlineRange> {
-    assert!(starts_with(src, "%", inline_range));
     let mut key = String::new();
     let mut value = String::new();
+    if !starts_with(src, "%", inline_range) {
+        return ParseResult {
+            value: (key, value),
+            errors: vec![Diagnostic::new(
+                "invalid-preamble",
+                head_char_range(src, &inline_range),
+                "expected preamble ('%').",
+            )],
+            warnings: vec![],
+            rest_range: inline_range,
+        };
+    }
     let mut rest_range = inline_range.consume("%".len());
 
     // key のパース
     loop {
         match pick_char(src, &rest_range) {
             Some(c) if c.is_ascii_whitespace() => {
-                rest_range.move_to_next_char();
+                move_to_next_char(src, &mut rest_range);
                 break;
             }
             Some(c) => {
                 key.push(c);
-                rest_range.move_to_next_char();
+                move_to_next_char(src, &mut rest_range);
             }
             None => {
                 return ParseResult {
                     value: (key, String::new()),
-                    errors: vec![format!(
-                        "at {}:{}: expected preamble value",
-                        rest_range.line, rest_range.start_column
+                    errors: vec![Diagnostic::new(
+                        "invalid-preamble",
+                        head_char_range(src, &rest_range),
+                        "expected preamble value",
                     )],
                     warnings: vec![],
                     rest_range,
@@ -134,7 +153,7 @@ fn parse_preamble(
         match pick_char(src, &rest_range) {
             Some(c) => {
                 value.push(c);
-                rest_range.move_to_next_char();
+                move_to_next_char(src, &mut rest_range);
             }
             None => {
                 return ParseResult {
@@ -149,31 +168,73 @@ fn parse_preamble(
 }
 
 const IMPORT_KEYWORD: &str = "import";
-fn parse_import(
-    src: &Vec<Vec<char>>,
+pub(crate) fn is_import_line(src: &Source, line: InlineRange) -> bool {
+    starts_with(src, IMPORT_KEYWORD, line)
+}
+
+pub(crate) fn parse_import(
+    src: &Source,
     inline_range: InlineRange,
 ) -> ParseResult<PathBuf, InlineRange> {
-    assert!(starts_with(src, IMPORT_KEYWORD, inline_range));
+    let result = parse_path_statement(src, inline_range, IMPORT_KEYWORD, "invalid-import");
+    let (path, selector) = result.value;
+    let mut errors = result.errors;
+    if selector.is_some() && errors.is_empty() {
+        errors.push(Diagnostic::new(
+            "invalid-import",
+            inline_range,
+            "import cannot select a part of a file; use `include` instead.",
+        ));
+    }
+    ParseResult {
+        value: path,
+        errors,
+        warnings: result.warnings,
+        rest_range: result.rest_range,
+    }
+}
+
+// `<keyword> 'path';` もしくは `<keyword> 'path#selector';` の形の行をパースする
+// エラーの識別子は `code` にする
+fn parse_path_statement(
+    src: &Source,
+    inline_range: InlineRange,
+    keyword: &str,
+    code: &'static str,
+) -> ParseResult<(PathBuf, Option<String>), InlineRange> {
     let mut path = PathBuf::new();
-    let mut rest_range = inline_range.consume(IMPORT_KEYWORD.len());
+    if !starts_with(src, keyword, inline_range) {
+        return ParseResult {
+            value: (path, None),
+            errors: vec![Diagnostic::new(
+                code,
+                head_char_range(src, &inline_range),
+                format!("expected `{}`.", keyword),
+            )],
+            warnings: vec![],
+            rest_range: inline_range,
+        };
+    }
+    let mut rest_range = inline_range.consume(keyword.len());
 
     // シングルクォートが来るまで空白を読み飛ばす
     // シングルクォートや空白以外が来たり終端が来たらエラーにする
     loop {
         match pick_char(src, &rest_range) {
             Some(c) if c.is_ascii_whitespace() => {
-                rest_range.move_to_next_char();
+                move_to_next_char(src, &mut rest_range);
             }
             Some('\'') => {
-                rest_range.move_to_next_char();
+                move_to_next_char(src, &mut rest_range);
                 break;
             }
             Some(c) => {
                 return ParseResult {
-                    value: path,
-                    errors: vec![format!(
-                        "at {}:{}: expected single quote ('), but actual is '{}'.",
-                        rest_range.line, rest_range.start_column, c
+                    value: (path, None),
+                    errors: vec![Diagnostic::new(
+                        code,
+                        head_char_range(src, &rest_range),
+                        format!("expected single quote ('), but actual is '{}'.", c),
                     )],
                     warnings: vec![],
                     rest_range,
@@ -181,10 +242,11 @@ fn parse_import(
             }
             None => {
                 return ParseResult {
-                    value: path,
-                    errors: vec![format!(
-                        "at {}:{}: expected single quote (').",
-                        rest_range.line, rest_range.start_column
+                    value: (path, None),
+                    errors: vec![Diagnostic::new(
+                        code,
+                        head_char_range(src, &rest_range),
+                        "expected single quote (').",
                     )],
                     warnings: vec![],
                     rest_range,
@@ -195,42 +257,81 @@ fn parse_import(
 
     // シングルクォートが来るまでインポートのパスを読み取る
     // '/' もしくは '\' が来たら push する
-    // 上記以外でローマアルファベット、数字、ハイフン ('-')、アンダースコア ('_') が来たらパス文字列に追加する
+    // 上記以外で文字 (Unicode の英字や漢字も含む)、数字、ハイフン ('-')、アンダースコア ('_')、ドット ('.') が来たらパス文字列に追加する
+    // '..' は親ディレクトリを表す。プロジェクトの根の外に出ないかは import 先を探すときに確かめる
+    // '#' が来たら、シングルクォートまでの残りを見出しや定理のタイトルを選ぶ文字列として読み取る
     // 上記以外もしくは終端が来たらエラーにする
     let mut buf = String::new();
+    let mut selector: Option<String> = None;
     loop {
-        match pick_char(src, &rest_range) {
-            Some('\'') => {
-                rest_range.move_to_next_char();
-                path.push(buf);
+        match (pick_char(src, &rest_range), selector.as_mut()) {
+            (Some('\''), Some(selector)) if selector.trim().is_empty() => {
+                return ParseResult {
+                    value: (path, None),
+                    errors: vec![Diagnostic::new(
+                        code,
+                        head_char_range(src, &rest_range),
+                        "selector after '#' is empty.",
+                    )],
+                    warnings: vec![],
+                    rest_range,
+                };
+            }
+            (Some('\''), Some(_)) => {
+                move_to_next_char(src, &mut rest_range);
                 break;
             }
-            Some('/') | Some('\\') => {
-                rest_range.move_to_next_char();
-                path.push(buf);
-                buf = String::new();
+            (Some(c), Some(selector)) => {
+                move_to_next_char(src, &mut rest_range);
+                selector.push(c);
+            }
+            (Some(c @ ('\'' | '#')), None) => {
+                path.push(std::mem::take(&mut buf));
+                if path.as_os_str().is_empty() {
+                    return ParseResult {
+                        value: (path, None),
+                        errors: vec![Diagnostic::new(
+                            code,
+                            head_char_range(src, &rest_range),
+                            "imported path is empty.",
+                        )],
+                        warnings: vec![],
+                        rest_range,
+                    };
+                }
+                move_to_next_char(src, &mut rest_range);
+                if c == '\'' {
+                    break;
+                }
+                selector = Some(String::new());
+            }
+            (Some('/' | '\\'), None) => {
+                move_to_next_char(src, &mut rest_range);
+                path.push(std::mem::take(&mut buf));
             }
-            Some(c) if c.is_ascii_alphanumeric() || c == '-' || c == '_' => {
-                rest_range.move_to_next_char();
+            (Some(c), None) if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' => {
+                move_to_next_char(src, &mut rest_range);
                 buf.push(c);
             }
-            Some(c) => {
+            (Some(c), None) => {
                 return ParseResult {
-                    value: PathBuf::new(),
-                    errors: vec![format!(
-                        "at {}:{}: '{}' is invalid character as imported path.",
-                        rest_range.line, rest_range.start_column, c
+                    value: (PathBuf::new(), None),
+                    errors: vec![Diagnostic::new(
+                        code,
+                        head_char_range(src, &rest_range),
+                        format!("'{}' is invalid character as imported path.", c),
                     )],
                     warnings: vec![],
                     rest_range,
                 }
             }
-            None => {
+            (None, _) => {
                 return ParseResult {
-                    value: PathBuf::new(),
-                    errors: vec![format!(
-                        "at {}:{}: expected single quote (').",
-                        rest_range.line, rest_range.start_column
+                    value: (PathBuf::new(), None),
+                    errors: vec![Diagnostic::new(
+                        code,
+                        head_char_range(src, &rest_range),
+                        "expected single quote (').",
                     )],
                     warnings: vec![],
                     rest_range,
@@ -244,18 +345,19 @@ fn parse_import(
     loop {
         match pick_char(src, &rest_range) {
             Some(c) if c.is_ascii_whitespace() => {
-                rest_range.move_to_next_char();
+                move_to_next_char(src, &mut rest_range);
             }
             Some(';') => {
-                rest_range.move_to_next_char();
+                move_to_next_char(src, &mut rest_range);
                 break;
             }
             Some(c) => {
                 return ParseResult {
-                    value: path,
-                    errors: vec![format!(
-                        "at {}:{}: expected semicolon (';'), but actual is '{}'.",
-                        rest_range.line, rest_range.start_column, c
+                    value: (path, None),
+                    errors: vec![Diagnostic::new(
+                        code,
+                        head_char_range(src, &rest_range),
+                        format!("expected semicolon (';'), but actual is '{}'.", c),
                     )],
                     warnings: vec![],
                     rest_range,
@@ -263,10 +365,11 @@ fn parse_import(
             }
             None => {
                 return ParseResult {
-                    value: path,
-                    errors: vec![format!(
-                        "at {}:{}: expected semicolon (';').",
-                        rest_range.line, rest_range.start_column
+                    value: (path, None),
+                    errors: vec![Diagnostic::new(
+                        code,
+                        head_char_range(src, &rest_range),
+                        "expected semicolon (';').",
                     )],
                     warnings: vec![],
                     rest_range,
@@ -280,17 +383,18 @@ fn parse_import(
     loop {
         match pick_char(src, &rest_range) {
             Some(c) if c.is_ascii_whitespace() => {
-                rest_range.move_to_next_char();
+                move_to_next_char(src, &mut rest_range);
             }
             None => {
                 break;
             }
             Some(c) => {
                 return ParseResult {
-                    value: path,
-                    errors: vec![format!(
-                        "at {}:{}: unexpected character '{}'.",
-                        rest_range.line, rest_range.start_column, c
+                    value: (path, None),
+                    errors: vec![Diagnostic::new(
+                        code,
+                        head_char_range(src, &rest_range),
+                        format!("unexpected character '{}'.", c),
                     )],
                     warnings: vec![],
                     rest_range,
@@ -300,7 +404,7 @@ fn parse_import(
     }
 
     ParseResult {
-        value: path,
+        value: (path, selector),
         errors: vec![],
         warnings: vec![],
         rest_range,
@@ -308,7 +412,7 @@ fn parse_import(
 }
 
 pub fn parse_block_elements(
-    src: &Vec<Vec<char>>,
+    src: &Source,
     src_range: BlockRange,
 ) -> ParseResult<Vec<BlockElement>, BlockRange> {
     let mut rest_range = src_range;
@@ -343,85 +447,269 @@ pub fn parse_block_elements(
     }
 }
 
-fn parse_block_element(src: &Vec<Vec<char>>, rest_range: BlockRange) -> ParseBlockElementResult {
-    assert!(!rest_range.is_empty());
-    let top_line_range = rest_range.front().expect("`rest_range` can not be empty");
-    assert!(!top_line_range.is_empty()); // 空行始まりではない
-
-    let result = parse_heading_block_element(src, &rest_range);
-    if result.value != BlockElement::ParseError {
-        return result;
-    }
+// 行の種類。ブロック要素の判定は各行の先頭だけを見て行う
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Empty,
+    Heading,
+    // "```" で始まる行
+    CodeFence,
+    // "$$" で始まる行 (数式ブロックの区切りは "$$" のみの行)
+    MathFence,
+    Theorem,
+    // "Proof." もしくは "proof." のみの行
+    Proof,
+    // 3 つ以上の '-' で始まる行 (推論規則の横線)
+    Rule,
+    ListItem,
+    Blockquote,
+    // "include '" で始まる行
+    Include,
+    // "excerpt '" で始まる行
+    Excerpt,
+    // 2 つ以上の空白で始まる行
+    Indented,
+    Text,
+}
 
-    let result = parse_code_block_element(src, &rest_range);
-    if result.value != BlockElement::ParseError {
-        return result;
+fn classify_line(src: &Source, line: InlineRange) -> LineKind {
+    if line.is_empty() {
+        LineKind::Empty
+    } else if starts_with(src, "#", line) {
+        LineKind::Heading
+    } else if starts_with(src, "```", line) {
+        LineKind::CodeFence
+    } else if starts_with(src, "$$", line) {
+        LineKind::MathFence
+    } else if MARK_TO_THEOREM_KIND
+        .iter()
+        .any(|(mark, _)| starts_with(src, mark, line))
+    {
+        LineKind::Theorem
+    } else if match_(src, "Proof.", line) || match_(src, "proof.", line) {
+        LineKind::Proof
+    } else if starts_with(src, "---", line) {
+        LineKind::Rule
+    } else if starts_with(src, "- ", line) {
+        LineKind::ListItem
+    } else if starts_with(src, "> ", line) {
+        LineKind::Blockquote
+    } else if is_path_statement(src, INCLUDE_KEYWORD, line) {
+        LineKind::Include
+    } else if is_path_statement(src, EXCERPT_KEYWORD, line) {
+        LineKind::Excerpt
+    } else if starts_with(src, "  ", line) {
+        LineKind::Indented
+    } else {
+        LineKind::Text
     }
+}
 
-    let result = parse_math_block_element(src, &rest_range);
-    if result.value != BlockElement::ParseError {
-        return result;
-    }
+// 先頭から連続する字下げされた行の数
+fn count_indented_lines(src: &Source, range: &BlockRange) -> usize {
+    range
+        .iter()
+        .take_while(|line| starts_with(src, "  ", **line))
+        .count()
+}
 
-    let result = parse_theorem_block_element(src, rest_range.clone());
-    if result.value != BlockElement::ParseError {
-        return result;
-    }
+// 推論規則は「字下げされた前提の行 (0 行以上)」「横線」の順に並ぶ
+// 先頭から連続する字下げされた行の直後が横線であれば推論規則の始まり
+fn starts_derivation(src: &Source, range: &BlockRange) -> bool {
+    let premise_count = count_indented_lines(src, range);
+    range
+        .get(premise_count)
+        .is_some_and(|line| classify_line(src, *line) == LineKind::Rule)
+}
 
-    let result = parse_proof_block_element(src, rest_range.clone());
-    if result.value != BlockElement::ParseError {
-        return result;
-    }
+// 先頭行を必ず 1 行以上読み進める。空の `rest_range` に対しては空の段落を返す
+pub(crate) fn parse_block_element(src: &Source, rest_range: BlockRange) -> ParseBlockElementResult {
+    let top_line_range = match rest_range.front() {
+        Some(line) => *line,
+        None => return parse_paragraph(src, rest_range),
+    };
 
-    let result = parse_derivation_block_element(src, rest_range.clone());
-    if let Some(derivation) = result.value {
-        return ParseBlockElementResult {
-            value: BlockElement::Derivation(derivation),
+    // 先頭行の種類で使うパーサを 1 つに決める
+    // 横線のない字下げ、"$$" のみではない "$$" 始まりの行は段落として扱う
+    let result = match classify_line(src, top_line_range) {
+        LineKind::Heading => return parse_heading_block_element(src, rest_range),
+        LineKind::CodeFence => parse_code_block_element(src, rest_range),
+        LineKind::MathFence => parse_math_block_element(src, rest_range),
+        LineKind::Theorem => return parse_theorem_block_element(src, rest_range),
+        LineKind::Proof => return parse_proof_block_element(src, rest_range),
+        LineKind::Rule | LineKind::Indented => {
+            let result = parse_derivation_block_element(src, rest_range);
+            if let Some(derivation) = result.value {
+                return ParseBlockElementResult {
+                    value: BlockElement::Derivation(derivation),
+                    errors: result.errors,
+                    warnings: result.warnings,
+                    rest_range: result.rest_range,
+                };
+            }
+            return parse_paragraph(src, result.rest_range);
+        }
+        LineKind::ListItem => return parse_list_block_element(src, rest_range),
+        LineKind::Blockquote => return parse_blockquote_element(src, rest_range),
+        LineKind::Include => return parse_include_block_element(src, rest_range),
+        LineKind::Excerpt => return parse_excerpt_block_element(src, rest_range),
+        LineKind::Empty | LineKind::Text => return parse_paragraph(src, rest_range),
+    };
+    match result.value {
+        Some(value) => ParseBlockElementResult {
+            value,
             errors: result.errors,
             warnings: result.warnings,
             rest_range: result.rest_range,
-        };
+        },
+        None => parse_paragraph(src, result.rest_range),
     }
+}
 
-    let result = parse_list_block_element(src, rest_range.clone());
-    if result.value != BlockElement::ParseError {
-        return result;
+// 先頭行が期待した種類の行でなかったときに、その 1 行を失敗した範囲として読み飛ばす
+fn parse_unexpected_line(
+    src: &Source,
+    mut rest_range: BlockRange,
+    message: &str,
+) -> ParseBlockElementResult {
+    let mut errors = vec![];
+    let mut lines = BlockRange::new();
+    if let Some(line) = rest_range.pop_front() {
+        errors.push(Diagnostic::new(
+            "unexpected-line",
+            head_char_range(src, &line),
+            message,
+        ));
+        lines.push_back(line);
     }
-
-    let result = parse_blockquote_element(src, rest_range.clone());
-    if result.value != BlockElement::ParseError {
-        return result;
+    ParseBlockElementResult {
+        value: BlockElement::ParseError {
+            lines,
+            message: message.to_string(),
+        },
+        errors,
+        warnings: vec![],
+        rest_range,
     }
+}
 
-    parse_paragraph(src, rest_range)
+const INCLUDE_KEYWORD: &str = "include";
+const EXCERPT_KEYWORD: &str = "excerpt";
+
+// `<keyword> '` で始まる行か。"include the ..." のような文章と区別するため、クォートまで見る
+fn is_path_statement(src: &Source, keyword: &str, line: InlineRange) -> bool {
+    starts_with(src, keyword, line)
+        && src
+            .slice(&line.consume(keyword.len()))
+            .trim_start()
+            .starts_with('\'')
 }
 
-fn parse_heading_block_element(
-    src: &Vec<Vec<char>>,
-    rest_range: &BlockRange,
+fn parse_include_block_element(src: &Source, rest_range: BlockRange) -> ParseBlockElementResult {
+    parse_path_statement_block_element(
+        src,
+        rest_range,
+        INCLUDE_KEYWORD,
+        "invalid-include",
+        |path, selector, range| {
+            BlockElement::Include(Include {
+                path,
+                selector,
+                range,
+                content: None,
+            })
+        },
+    )
+}
+
+fn parse_excerpt_block_element(src: &Source, rest_range: BlockRange) -> ParseBlockElementResult {
+    parse_path_statement_block_element(
+        src,
+        rest_range,
+        EXCERPT_KEYWORD,
+        "invalid-excerpt",
+        |path, selector, range| BlockElement::Code {
+            lines: BlockRange::new(),
+            language: language_of(&path),
+            excerpt: Some(Excerpt {
+                path,
+                selector,
+                range,
+                source: None,
+                text: None,
+            }),
+        },
+    )
+}
+
+// 先頭行を `<keyword> 'path';` としてパースし、`make` でブロック要素にする
+// パースに失敗したら、その行を失敗した範囲にする
+fn parse_path_statement_block_element(
+    src: &Source,
+    mut rest_range: BlockRange,
+    keyword: &str,
+    code: &'static str,
+    make: impl FnOnce(PathBuf, Option<String>, InlineRange) -> BlockElement,
 ) -> ParseBlockElementResult {
-    let parse_error = ParseBlockElementResult {
-        value: BlockElement::ParseError,
-        errors: vec![],
-        warnings: vec![],
-        rest_range: rest_range.clone(),
+    let Some(line) = rest_range.pop_front() else {
+        return parse_paragraph(src, rest_range);
+    };
+    let result = parse_path_statement(src, line, keyword, code);
+    let value = if result.errors.is_empty() {
+        let (path, selector) = result.value;
+        make(path, selector, line)
+    } else {
+        BlockElement::ParseError {
+            lines: [line].into_iter().collect(),
+            message: result.errors[0].message.clone(),
+        }
+    };
+    ParseBlockElementResult {
+        value,
+        errors: result.errors,
+        warnings: result.warnings,
+        rest_range,
+    }
+}
+
+// 拡張子から決めたコードの言語の名前
+fn language_of(path: &std::path::Path) -> Option<String> {
+    let extension = path.extension()?.to_str()?;
+    let language = match extension {
+        "rs" => "rust",
+        "py" => "python",
+        "js" | "mjs" => "javascript",
+        "ts" => "typescript",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" => "cpp",
+        "hs" => "haskell",
+        "ml" | "mli" => "ocaml",
+        "sh" => "bash",
+        "bok" => "bokuteki",
+        "md" => "markdown",
+        "yml" => "yaml",
+        other => other,
     };
+    Some(language.to_string())
+}
 
-    let mut rest_range = rest_range.clone();
+fn parse_heading_block_element(
+    src: &Source,
+    mut rest_range: BlockRange,
+) -> ParseBlockElementResult {
     if let Some(mut line_rest_range) = rest_range.pop_front() {
         if starts_with(src, "#", line_rest_range) {
             let mut level = 0;
             while starts_with(src, "#", line_rest_range) {
-                line_rest_range.move_to_next_char();
+                move_to_next_char(src, &mut line_rest_range);
                 level += 1;
             }
 
             let inline_elements_result = parse_inline_elements(src, line_rest_range);
-            assert!(inline_elements_result.rest_range.is_empty());
             let errors = inline_elements_result.errors;
             let warnings = inline_elements_result.warnings;
 
-            ParseBlockElementResult {
+            return ParseBlockElementResult {
                 value: BlockElement::Heading {
                     level,
                     content: inline_elements_result.value,
@@ -429,35 +717,37 @@ fn parse_heading_block_element(
                 errors,
                 warnings,
                 rest_range,
-            }
-        } else {
-            parse_error
+            };
         }
-    } else {
-        parse_error
+        rest_range.push_front(line_rest_range);
     }
+    parse_unexpected_line(src, rest_range, "expected heading ('#').")
 }
 
 fn parse_code_block_element(
-    src: &Vec<Vec<char>>,
-    rest_range: &BlockRange,
-) -> ParseBlockElementResult {
-    fn check_start_line(src: &Vec<Vec<char>>, line: InlineRange) -> Option<()> {
-        if starts_with(src, "```", line.clone()) {
+    src: &Source,
+    rest_range: BlockRange,
+) -> ParseResult<Option<BlockElement>, BlockRange> {
+    fn check_start_line(src: &Source, line: InlineRange) -> Option<()> {
+        if starts_with(src, "```", line) {
             Some(())
         } else {
             None
         }
     }
-    fn check_end_line(src: &Vec<Vec<char>>, line: InlineRange) -> Option<()> {
-        if match_(src, "```", line.clone()) {
+    fn check_end_line(src: &Source, line: InlineRange) -> Option<()> {
+        if match_(src, "```", line) {
             Some(())
         } else {
             None
         }
     }
     fn make_code_block(range: BlockRange, _: (), _: ()) -> BlockElement {
-        BlockElement::Code { lines: range }
+        BlockElement::Code {
+            lines: range,
+            language: None,
+            excerpt: None,
+        }
     }
     parse_surrounded_block_element(
         src,
@@ -465,22 +755,24 @@ fn parse_code_block_element(
         check_start_line,
         check_end_line,
         make_code_block,
+        "code block",
+        "```",
     )
 }
 
 fn parse_math_block_element(
-    src: &Vec<Vec<char>>,
-    rest_range: &BlockRange,
-) -> ParseBlockElementResult {
-    fn check_start_line(src: &Vec<Vec<char>>, line: InlineRange) -> Option<()> {
-        if match_(src, "$$", line.clone()) {
+    src: &Source,
+    rest_range: BlockRange,
+) -> ParseResult<Option<BlockElement>, BlockRange> {
+    fn check_start_line(src: &Source, line: InlineRange) -> Option<()> {
+        if match_(src, "$$", line) {
             Some(())
         } else {
             None
         }
     }
-    fn check_end_line(src: &Vec<Vec<char>>, line: InlineRange) -> Option<()> {
-        if match_(src, "$$", line.clone()) {
+    fn check_end_line(src: &Source, line: InlineRange) -> Option<()> {
+        if match_(src, "$$", line) {
             Some(())
         } else {
             None
@@ -495,63 +787,85 @@ fn parse_math_block_element(
         check_start_line,
         check_end_line,
         make_math_block,
+        "math block",
+        "$$",
     )
 }
 
 fn parse_surrounded_block_element<T, U>(
-    src: &Vec<Vec<char>>,
-    rest_range: &BlockRange,
-    check_start_line: fn(src: &Vec<Vec<char>>, InlineRange) -> Option<T>,
-    check_end_line: fn(src: &Vec<Vec<char>>, InlineRange) -> Option<U>,
+    src: &Source,
+    mut rest_range: BlockRange,
+    check_start_line: fn(src: &Source, InlineRange) -> Option<T>,
+    check_end_line: fn(src: &Source, InlineRange) -> Option<U>,
     make_func: fn(BlockRange, T, U) -> BlockElement,
-) -> ParseBlockElementResult {
-    let mut rest_range = rest_range.clone();
-    let parse_error = ParseBlockElementResult {
-        value: BlockElement::ParseError,
-        errors: vec![],
-        warnings: vec![],
-        rest_range: rest_range.clone(),
-    };
-    let errors = vec![];
+    name: &str,
+    end_mark: &str,
+) -> ParseResult<Option<BlockElement>, BlockRange> {
+    let mut errors = vec![];
     let warnings = vec![];
 
-    if let Some(line) = rest_range.pop_front() {
-        if let Some(t) = check_start_line(src, line) {
-            let mut content_lines = VecDeque::new();
-            loop {
-                if let Some(line) = rest_range.pop_front() {
-                    if let Some(u) = check_end_line(src, line) {
-                        return ParseBlockElementResult {
-                            value: make_func(content_lines, t, u),
-                            errors,
-                            warnings,
-                            rest_range,
-                        };
+    // 終端行を探してから取り出すので、失敗したときに `rest_range` を複製しなくてすむ
+    let start = rest_range
+        .front()
+        .and_then(|line| check_start_line(src, *line));
+    let end = rest_range
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find_map(|(idx, line)| check_end_line(src, *line).map(|u| (idx, u)));
+    let value = match (start, end) {
+        (Some(t), Some((end_idx, u))) => {
+            let mut content_lines: BlockRange = rest_range.drain(..=end_idx).collect();
+            content_lines.pop_front();
+            content_lines.pop_back();
+            Some(make_func(content_lines, t, u))
+        }
+        // 終端がなければ開始行だけを失敗した範囲にする
+        // 残りの行を飲み込まず、後続のブロック要素はそのままパースさせる
+        (Some(_), None) => {
+            let last_line = rest_range.back().copied();
+            rest_range.pop_front().map(|start_line| {
+                let message = format!("unterminated {}", name);
+                let mut error = Diagnostic::new("unterminated-block", start_line, message.clone());
+                if let Some(last_line) = last_line {
+                    let end_of_range = InlineRange {
+                        start_column: last_line.end_column,
+                        ..last_line
+                    };
+                    let place = if last_line.line + 1 == src.line_count() {
+                        "file"
                     } else {
-                        content_lines.push_back(line);
-                    }
-                } else {
-                    return parse_error;
+                        "enclosing block"
+                    };
+                    error = error.with_label(
+                        end_of_range,
+                        format!("reached end of {} without closing \"{}\"", place, end_mark),
+                    );
                 }
-            }
-        } else {
-            parse_error
+                errors.push(error.with_note(format!(
+                    "the closing \"{}\" must be on a line of its own",
+                    end_mark
+                )));
+                BlockElement::ParseError {
+                    lines: BlockRange::from([start_line]),
+                    message,
+                }
+            })
         }
-    } else {
-        parse_error
+        (None, _) => None,
+    };
+    ParseResult {
+        value,
+        errors,
+        warnings,
+        rest_range,
     }
 }
 
 fn parse_theorem_block_element(
-    src: &Vec<Vec<char>>,
+    src: &Source,
     mut rest_range: BlockRange,
 ) -> ParseBlockElementResult {
-    let parse_error = ParseBlockElementResult {
-        value: BlockElement::ParseError,
-        errors: vec![],
-        warnings: vec![],
-        rest_range: rest_range.clone(),
-    };
     let mut errors = vec![];
     let mut warnings = vec![];
 
@@ -560,22 +874,21 @@ fn parse_theorem_block_element(
         errors.append(&mut kind_result.errors);
         warnings.append(&mut kind_result.warnings);
         if kind_result.value == TheoremKind::ParseError {
-            return parse_error;
+            rest_range.push_front(line);
+            return parse_unexpected_line(src, rest_range, "expected theorem.");
         }
         let mut inline_elements_result = parse_inline_elements(src, kind_result.rest_range);
-        assert!(inline_elements_result.rest_range.is_empty());
         errors.append(&mut inline_elements_result.errors);
         warnings.append(&mut inline_elements_result.warnings);
         (kind_result.value, inline_elements_result.value)
     } else {
-        return parse_error;
+        return parse_unexpected_line(src, rest_range, "expected theorem.");
     };
 
     let inner_range = lift_block_range(src, "  ", rest_range);
     rest_range = inner_range.rest_range;
 
     let mut inner_result = parse_block_elements(src, inner_range.value);
-    assert!(inner_result.rest_range.is_empty());
     errors.append(&mut inner_result.errors);
     warnings.append(&mut inner_result.warnings);
 
@@ -590,7 +903,7 @@ fn parse_theorem_block_element(
         rest_range,
     }
 }
-const MARK_TO_THEOREM_KIND: [(&'static str, TheoremKind); 26] = [
+const MARK_TO_THEOREM_KIND: [(&str, TheoremKind); 26] = [
     // 定理
     ("Theorem. ", TheoremKind::Theorem),
     ("theorem. ", TheoremKind::Theorem),
@@ -626,11 +939,11 @@ const MARK_TO_THEOREM_KIND: [(&'static str, TheoremKind); 26] = [
 ];
 
 fn parse_theorem_kind(
-    src: &Vec<Vec<char>>,
+    src: &Source,
     rest_range: &InlineRange,
 ) -> ParseResult<TheoremKind, InlineRange> {
     for (mark, kind) in &MARK_TO_THEOREM_KIND {
-        if starts_with(src, mark, rest_range.clone()) {
+        if starts_with(src, mark, *rest_range) {
             return ParseResult {
                 value: *kind,
                 errors: vec![],
@@ -644,30 +957,20 @@ fn parse_theorem_kind(
         value: TheoremKind::ParseError,
         errors: vec![],
         warnings: vec![],
-        rest_range: rest_range.clone(),
+        rest_range: *rest_range,
     }
 }
 
-fn parse_proof_block_element(
-    src: &Vec<Vec<char>>,
-    mut rest_range: BlockRange,
-) -> ParseBlockElementResult {
-    let parse_error = ParseBlockElementResult {
-        value: BlockElement::ParseError,
-        errors: vec![],
-        warnings: vec![],
-        rest_range: rest_range.clone(),
-    };
+fn parse_proof_block_element(src: &Source, mut rest_range: BlockRange) -> ParseBlockElementResult {
     let mut errors = vec![];
     let mut warnings = vec![];
 
-    if let Some(line) = rest_range.pop_front() {
-        if !match_(src, "Proof.", line) && !match_(src, "proof.", line) {
-            return parse_error;
+    match rest_range.front() {
+        Some(line) if match_(src, "Proof.", *line) || match_(src, "proof.", *line) => {
+            rest_range.pop_front();
         }
-    } else {
-        return parse_error;
-    };
+        _ => return parse_unexpected_line(src, rest_range, "expected proof ('Proof.')."),
+    }
 
     let mut inner_range = lift_block_range(src, "  ", rest_range);
     errors.append(&mut inner_range.errors);
@@ -675,7 +978,6 @@ fn parse_proof_block_element(
     rest_range = inner_range.rest_range;
 
     let mut inner_result = parse_block_elements(src, inner_range.value);
-    assert!(inner_result.rest_range.is_empty());
     errors.append(&mut inner_result.errors);
     warnings.append(&mut inner_result.warnings);
 
@@ -690,51 +992,57 @@ fn parse_proof_block_element(
 }
 
 fn parse_derivation_block_element(
-    src: &Vec<Vec<char>>,
+    src: &Source,
     mut rest_range: BlockRange,
 ) -> ParseResult<Option<Derivation>, BlockRange> {
-    let parse_error = ParseResult {
-        value: None,
-        errors: vec![],
-        warnings: vec![],
-        rest_range: rest_range.clone(),
-    };
     let mut errors = vec![];
     let mut warnings = vec![];
 
+    // 前提の直後の行が横線でなければ推論規則ではない
+    let premise_count = count_indented_lines(src, &rest_range);
+    let rule_line = match rest_range.get(premise_count) {
+        Some(line) if classify_line(src, *line) == LineKind::Rule => *line,
+        _ => {
+            return ParseResult {
+                value: None,
+                errors,
+                warnings,
+                rest_range,
+            };
+        }
+    };
+
     let mut premises_range_result = lift_block_range(src, "  ", rest_range);
     errors.append(&mut premises_range_result.errors);
     warnings.append(&mut premises_range_result.warnings);
     let mut premises: Vec<Derivation> = vec![];
     let mut premises_rest_range = premises_range_result.value;
     while !premises_rest_range.is_empty() {
-        let mut premise_result = parse_derivation_block_element(src, premises_rest_range.clone());
-        if let Some(premise) = premise_result.value {
+        if starts_derivation(src, &premises_rest_range) {
+            let mut premise_result = parse_derivation_block_element(src, premises_rest_range);
             errors.append(&mut premise_result.errors);
             warnings.append(&mut premise_result.warnings);
-            premises.push(premise);
+            premises.extend(premise_result.value);
             premises_rest_range = premise_result.rest_range;
-        } else if let Some(line_range) = premises_rest_range.pop_front() {
-            let inline_elements = parse_inline_elements(src, line_range);
-            premises.push(Derivation::Leaf(inline_elements.value));
+        } else {
+            // 横線の続かない字下げは 1 行ずつ葉になる
+            let leaf_count = count_indented_lines(src, &premises_rest_range).max(1);
+            for line_range in premises_rest_range.drain(..leaf_count) {
+                let mut inline_elements_result = parse_inline_elements(src, line_range);
+                errors.append(&mut inline_elements_result.errors);
+                warnings.append(&mut inline_elements_result.warnings);
+                premises.push(Derivation::Leaf(inline_elements_result.value));
+            }
         }
     }
     rest_range = premises_range_result.rest_range;
 
-    let mut rule_name_result = if let Some(mut line_range) = rest_range.pop_front() {
-        let mut count = 0;
-        while check_at(src, '-', &line_range) {
-            count += 1;
-            line_range.move_to_next_char();
-        }
-        if count >= 3 {
-            parse_inline_elements(src, line_range)
-        } else {
-            return parse_error;
-        }
-    } else {
-        return parse_error;
-    };
+    rest_range.pop_front();
+    let mut rule_name_range = rule_line;
+    while check_at(src, '-', &rule_name_range) {
+        move_to_next_char(src, &mut rule_name_range);
+    }
+    let mut rule_name_result = parse_inline_elements(src, rule_name_range);
     errors.append(&mut rule_name_result.errors);
     warnings.append(&mut rule_name_result.warnings);
     let rule_name = rule_name_result.value;
@@ -763,13 +1071,17 @@ fn parse_derivation_block_element(
     }
 }
 
-fn parse_list_block_element(
-    src: &Vec<Vec<char>>,
-    mut rest_range: BlockRange,
-) -> ParseBlockElementResult {
+fn parse_list_block_element(src: &Source, mut rest_range: BlockRange) -> ParseBlockElementResult {
     let mut errors = vec![];
     let mut warnings = vec![];
 
+    if !rest_range
+        .front()
+        .is_some_and(|line| starts_with(src, "- ", *line))
+    {
+        return parse_unexpected_line(src, rest_range, "expected list item ('- ').");
+    }
+
     let mut items = vec![];
     while let Some(line) = rest_range.pop_front() {
         if !starts_with(src, "- ", line) {
@@ -797,13 +1109,9 @@ fn parse_list_block_element(
     }
 
     ParseBlockElementResult {
-        value: if items.is_empty() {
-            BlockElement::ParseError
-        } else {
-            BlockElement::List {
-                mark_kind: ListMarkKind::Bullet,
-                items,
-            }
+        value: BlockElement::List {
+            mark_kind: ListMarkKind::Bullet,
+            items,
         },
         errors,
         warnings,
@@ -811,118 +1119,91 @@ fn parse_list_block_element(
     }
 }
 
-fn parse_blockquote_element(
-    src: &Vec<Vec<char>>,
-    mut rest_range: BlockRange,
-) -> ParseBlockElementResult {
-    let parse_error = ParseBlockElementResult {
-        value: BlockElement::ParseError,
-        errors: vec![],
-        warnings: vec![],
-        rest_range: rest_range.clone(),
-    };
+fn parse_blockquote_element(src: &Source, mut rest_range: BlockRange) -> ParseBlockElementResult {
     let mut errors = vec![];
     let mut warnings = vec![];
 
-    if let Some(line) = rest_range.pop_front() {
+    let mut inner_range = BlockRange::new();
+    while let Some(line) = rest_range.pop_front() {
         if starts_with(src, "> ", line) {
-            let mut inner_range = BlockRange::new();
             inner_range.push_back(line.consume(2));
-            loop {
-                if let Some(line) = rest_range.pop_front() {
-                    if starts_with(src, "> ", line) {
-                        inner_range.push_back(line.consume(2));
-                    } else {
-                        rest_range.push_front(line);
-                        break;
-                    }
-                } else {
-                    break;
-                }
-            }
-
-            let mut inner_result = parse_block_elements(src, inner_range);
-            let inner = inner_result.value;
-            assert!(inner_result.rest_range.is_empty());
-            errors.append(&mut inner_result.errors);
-            warnings.append(&mut inner_result.warnings);
-
-            ParseBlockElementResult {
-                value: BlockElement::Blockquote { inner },
-                errors,
-                warnings,
-                rest_range,
-            }
         } else {
-            parse_error
+            rest_range.push_front(line);
+            break;
         }
-    } else {
-        parse_error
+    }
+    if inner_range.is_empty() {
+        return parse_unexpected_line(src, rest_range, "expected blockquote ('> ').");
+    }
+
+    let mut inner_result = parse_block_elements(src, inner_range);
+    let inner = inner_result.value;
+    errors.append(&mut inner_result.errors);
+    warnings.append(&mut inner_result.warnings);
+
+    ParseBlockElementResult {
+        value: BlockElement::Blockquote { inner },
+        errors,
+        warnings,
+        rest_range,
     }
 }
 
-fn parse_paragraph(src: &Vec<Vec<char>>, mut rest_range: BlockRange) -> ParseBlockElementResult {
+fn parse_paragraph(src: &Source, mut rest_range: BlockRange) -> ParseBlockElementResult {
     let mut inline_elements = vec![];
     let mut errors = vec![];
     let mut warnings = vec![];
 
-    let head_line = rest_range.front().cloned();
-
     // ソース終端もしくは別のブロック要素の始まりまで Inline 要素をパースする
-    while !is_paragraph_end(src, &rest_range, &head_line) {
-        let rest_line_range = rest_range.pop_front().expect("can not be empty");
-        let mut inline_elements_result = parse_inline_elements(src, rest_line_range);
-        assert!(inline_elements_result.rest_range.is_empty());
-        errors.append(&mut inline_elements_result.errors);
-        warnings.append(&mut inline_elements_result.warnings);
-        inline_elements.append(&mut &mut inline_elements_result.value);
-    }
-
     // 段落の終わりとは以下のどれか
     // - ソース終端
     // - 別のブロック要素の始まり
     //   - 見出しブロックの始まり
     //   - 段落の区切り行（空行）
     //   - コードブロックの始まり
-    //   - 数式ブロックの始まり
+    //   - 数式ブロックの始まり ("$$" で始まる行。"$$x$$" のように "$$" のみの行でなくても段落を終え、
+    //     その行は次の段落の 1 行目になる)
     //   - 定理ブロックの始まり
     //   - 証明ブロックの始まり
+    //   - 推論規則の始まり
     //   - リストブロックの始まり
     //   - 引用ブロックの始まり
-    // ただしコードブロックの終端マーク ("```") もしくは 数式ブロックの終端マーク ("$$") が1行目に出現した場合は、それは段落の終わりではない。
-    fn is_paragraph_end(
-        src: &Vec<Vec<char>>,
-        rest_range: &BlockRange,
-        head_line: &Option<InlineRange>,
-    ) -> bool {
-        if parse_derivation_block_element(src, rest_range.clone())
-            .value
-            .is_some()
-        {
-            return true;
-        }
-        if let Some(line_range) = rest_range.front() {
-            starts_with(src, "#", *line_range)
-                || line_range.is_empty()
-                || if let Some(head_line) = head_line {
-                    if head_line != line_range {
-                        starts_with(src, "```", *line_range) || starts_with(src, "$$", *line_range)
-                    } else {
-                        false
-                    }
-                } else {
-                    false
+    //   - include の行
+    //   - excerpt の行
+    // ただし 1 行目は種類によらず段落に含める (コードブロックの終端マーク ("```") や
+    // 数式ブロックの終端マーク ("$$") が 1 行目に出現した場合など)。これで必ず 1 行以上読み進める
+    let mut is_head_line = true;
+    while let Some(line_range) = rest_range.front() {
+        let line_count = match classify_line(src, *line_range) {
+            LineKind::Indented => {
+                // 連続する字下げ行の直後に横線があれば推論規則の始まり
+                // なければ字下げ行はまとめて段落に含まれる
+                if !is_head_line && starts_derivation(src, &rest_range) {
+                    break;
                 }
-                || MARK_TO_THEOREM_KIND
-                    .iter()
-                    .any(|(mark, _)| starts_with(src, mark, *line_range))
-                || starts_with(src, "Proof.", *line_range)
-                || starts_with(src, "proof.", *line_range)
-                || starts_with(src, "- ", *line_range)
-                || starts_with(src, "> ", *line_range)
-        } else {
-            true
+                count_indented_lines(src, &rest_range)
+            }
+            _ if is_head_line => 1,
+            LineKind::Empty
+            | LineKind::Heading
+            | LineKind::Theorem
+            | LineKind::Proof
+            | LineKind::Rule
+            | LineKind::ListItem
+            | LineKind::Blockquote
+            | LineKind::Include
+            | LineKind::Excerpt
+            | LineKind::CodeFence
+            | LineKind::MathFence => break,
+            LineKind::Text => 1,
+        };
+        for line_range in rest_range.drain(..line_count) {
+            let mut inline_elements_result = parse_inline_elements(src, line_range);
+            errors.append(&mut inline_elements_result.errors);
+            warnings.append(&mut inline_elements_result.warnings);
+            inline_elements.append(&mut inline_elements_result.value);
         }
+        is_head_line = false;
     }
 
     ParseBlockElementResult {
@@ -936,21 +1217,17 @@ fn parse_paragraph(src: &Vec<Vec<char>>, mut rest_range: BlockRange) -> ParseBlo
 }
 
 fn lift_block_range(
-    src: &Vec<Vec<char>>,
+    src: &Source,
     prefix: &str,
     range: BlockRange,
 ) -> ParseResult<BlockRange, BlockRange> {
     let mut rest_range = range;
     let mut lifted_range = BlockRange::new();
-    loop {
-        if let Some(line) = rest_range.pop_front() {
-            if starts_with(src, prefix, line) {
-                lifted_range.push_back(line.consume(prefix.len()));
-            } else {
-                rest_range.push_front(line);
-                break;
-            }
+    while let Some(line) = rest_range.pop_front() {
+        if starts_with(src, prefix, line) {
+            lifted_range.push_back(line.consume(prefix.len()));
         } else {
+            rest_range.push_front(line);
             break;
         }
     }
@@ -962,19 +1239,93 @@ fn lift_block_range(
     }
 }
 
+// インライン要素の区切り文字
+const DELIMITERS: [char; 5] = ['$', '`', '%', ']', ')'];
+
+// 行内の各位置から見て次に区切り文字が現れる列の表
+// 行を後ろから 1 度なめて作っておけば、インライン要素が閉じているかを O(1) で判定できる
+struct Delimiters {
+    start_column: usize,
+    // next[i][k] は `start_column + i` 列以降で最初に `DELIMITERS[k]` が現れる列 (なければ None)
+    next: Vec<[Option<usize>; DELIMITERS.len()]>,
+}
+
+impl Delimiters {
+    fn new(src: &Source, range: InlineRange) -> Self {
+        // 区切り文字はすべて ASCII なので、バイト単位で見てもマルチバイト文字の途中と取り違えない
+        let bytes = src.slice(&range).as_bytes();
+        let mut next = vec![[None; DELIMITERS.len()]; bytes.len() + 1];
+        for (idx, byte) in bytes.iter().enumerate().rev() {
+            next[idx] = next[idx + 1];
+            if let Some(k) = DELIMITERS
+                .iter()
+                .position(|delimiter| *delimiter as u32 == *byte as u32)
+            {
+                next[idx][k] = Some(range.start_column + idx);
+            }
+        }
+        Delimiters {
+            start_column: range.start_column,
+            next,
+        }
+    }
+
+    // `range` の中で最初に `delimiter` が現れる列
+    fn find(&self, delimiter: char, range: &InlineRange) -> Option<usize> {
+        let k = DELIMITERS.iter().position(|c| *c == delimiter)?;
+        self.next
+            .get(range.start_column.checked_sub(self.start_column)?)
+            .and_then(|next| next[k])
+            .filter(|column| *column < range.end_column)
+    }
+}
+
 fn parse_inline_elements(
-    src: &Vec<Vec<char>>,
+    src: &Source,
+    rest_range: InlineRange,
+) -> ParseResult<Vec<InlineElement>, InlineRange> {
+    let delimiters = Delimiters::new(src, rest_range);
+    parse_inline_elements_with(src, &delimiters, rest_range)
+}
+
+// 行を先頭から 1 度だけ走査し、インライン要素が始まる位置で区切ってテキストを切り出す
+fn parse_inline_elements_with(
+    src: &Source,
+    delimiters: &Delimiters,
     mut rest_range: InlineRange,
 ) -> ParseResult<Vec<InlineElement>, InlineRange> {
     let mut inline_elements = vec![];
     let mut errors = vec![];
     let mut warnings = vec![];
+    let mut text_start_column = rest_range.start_column;
     while !rest_range.is_empty() {
-        let mut result = parse_inline_element(src, rest_range);
-        inline_elements.push(result.value);
-        errors.append(&mut result.errors);
-        warnings.append(&mut result.warnings);
-        rest_range = result.rest_range;
+        if let Some(mut result) = parse_inline_element(src, delimiters, rest_range) {
+            if text_start_column < rest_range.start_column {
+                inline_elements.push(InlineElement::Text {
+                    range: InlineRange {
+                        line: rest_range.line,
+                        start_column: text_start_column,
+                        end_column: rest_range.start_column,
+                    },
+                });
+            }
+            inline_elements.push(result.value);
+            errors.append(&mut result.errors);
+            warnings.append(&mut result.warnings);
+            rest_range = result.rest_range;
+            text_start_column = rest_range.start_column;
+        } else {
+            move_to_next_char(src, &mut rest_range);
+        }
+    }
+    if text_start_column < rest_range.start_column {
+        inline_elements.push(InlineElement::Text {
+            range: InlineRange {
+                line: rest_range.line,
+                start_column: text_start_column,
+                end_column: rest_range.start_column,
+            },
+        });
     }
     ParseResult {
         value: inline_elements,
@@ -984,350 +1335,141 @@ fn parse_inline_elements(
     }
 }
 
+// `rest_range` の先頭から始まるテキスト以外のインライン要素をパースする
 fn parse_inline_element(
-    src: &Vec<Vec<char>>,
-    mut rest_range: InlineRange,
-) -> ParseInlineElementResult {
-    let result = parse_inline_link_element(src, &rest_range);
-    if !result.value.is_parse_error() {
-        return result;
-    }
-
-    let result = parse_inline_math_element(src, &rest_range);
-    if !result.value.is_parse_error() {
-        return result;
-    }
-
-    let result = parse_inline_code_element(src, &rest_range);
-    if !result.value.is_parse_error() {
-        return result;
-    }
-
-    let result = parse_inline_small_caps_element(src, &rest_range);
-    if !result.value.is_parse_error() {
-        return result;
-    }
-
-    let start_column = rest_range.start_column;
-    let errors = vec![];
-    let warnings = vec![];
-    while !rest_range.is_empty() {
-        if !parse_inline_link_element(src, &rest_range)
-            .value
-            .is_parse_error()
-            || !parse_inline_math_element(src, &rest_range)
-                .value
-                .is_parse_error()
-            || !parse_inline_code_element(src, &rest_range)
-                .value
-                .is_parse_error()
-            || !parse_inline_small_caps_element(src, &rest_range)
-                .value
-                .is_parse_error()
-        {
-            let inline_range = InlineRange {
-                line: rest_range.line,
-                start_column,
-                end_column: rest_range.start_column,
-            };
-            return ParseInlineElementResult {
-                value: InlineElement::Text {
-                    range: inline_range,
-                },
-                errors,
-                warnings,
+    src: &Source,
+    delimiters: &Delimiters,
+    rest_range: InlineRange,
+) -> Option<ParseInlineElementResult> {
+    match pick_char(src, &rest_range)? {
+        '[' => parse_inline_link_element(src, delimiters, rest_range),
+        '$' => parse_inline_surrounded_element(src, delimiters, '$', rest_range, |range| {
+            InlineElement::Math { range }
+        })
+        .or_else(|| {
+            Some(parse_unclosed_delimiter(
+                src,
                 rest_range,
-            };
-        }
-        rest_range.move_to_next_char();
-    }
-
-    ParseInlineElementResult {
-        value: InlineElement::Text {
-            range: InlineRange {
-                line: rest_range.line,
-                start_column,
-                end_column: rest_range.start_column,
-            },
-        },
-        errors,
-        warnings,
-        rest_range,
+                "unclosed inline math ('$').",
+            ))
+        }),
+        '`' => parse_inline_surrounded_element(src, delimiters, '`', rest_range, |range| {
+            InlineElement::Code { range }
+        })
+        .or_else(|| {
+            Some(parse_unclosed_delimiter(
+                src,
+                rest_range,
+                "unclosed inline code ('`').",
+            ))
+        }),
+        '%' => parse_inline_surrounded_element(src, delimiters, '%', rest_range, |range| {
+            InlineElement::SmallCaps { range }
+        }),
+        _ => None,
     }
 }
 
 fn parse_inline_link_element(
-    src: &Vec<Vec<char>>,
-    rest_range: &InlineRange,
-) -> ParseInlineElementResult {
-    let mut rest_range = rest_range.clone();
-    let mut errors = vec![];
-    let mut warnings = vec![];
-    if let Some('[') = pick_char(src, &rest_range) {
-        rest_range.move_to_next_char();
-    } else {
-        return ParseInlineElementResult {
-            value: InlineElement::ParseError,
-            errors,
-            warnings,
-            rest_range,
-        };
+    src: &Source,
+    delimiters: &Delimiters,
+    rest_range: InlineRange,
+) -> Option<ParseInlineElementResult> {
+    let text_range = InlineRange {
+        start_column: rest_range.start_column + 1,
+        end_column: delimiters.find(']', &rest_range.consume(1))?,
+        ..rest_range
+    };
+    let url_start_range = rest_range.consume(text_range.end_column - rest_range.start_column + 1);
+    if !check_at(src, '(', &url_start_range) || url_start_range.is_empty() {
+        return None;
     }
+    let url_range = InlineRange {
+        start_column: url_start_range.start_column + 1,
+        end_column: delimiters.find(')', &url_start_range.consume(1))?,
+        ..rest_range
+    };
 
-    let text_start_column = rest_range.start_column;
-    loop {
-        match pick_char(src, &rest_range) {
-            Some(']') => {
-                break;
-            }
-            Some(_) => {
-                rest_range.move_to_next_char();
-            }
-            None => {
-                return ParseInlineElementResult {
-                    value: InlineElement::ParseError,
-                    errors,
-                    warnings,
-                    rest_range,
-                }
-            }
-        }
-    }
-    let text_end_column = rest_range.start_column;
-    rest_range.move_to_next_char();
-    let mut text_result = parse_inline_elements(
-        src,
-        InlineRange {
-            line: rest_range.line,
-            start_column: text_start_column,
-            end_column: text_end_column,
+    let text_result = parse_inline_elements_with(src, delimiters, text_range);
+    Some(ParseInlineElementResult {
+        value: InlineElement::Link {
+            text: text_result.value,
+            url_range,
         },
-    );
-    errors.append(&mut text_result.errors);
-    warnings.append(&mut text_result.warnings);
-    if !text_result.rest_range.is_empty() {
-        return ParseInlineElementResult {
-            value: InlineElement::ParseError,
-            errors,
-            warnings,
-            rest_range,
-        };
-    }
-    let text = text_result.value;
+        errors: text_result.errors,
+        warnings: text_result.warnings,
+        rest_range: rest_range.consume(url_range.end_column - rest_range.start_column + 1),
+    })
+}
 
-    if let Some('(') = pick_char(src, &rest_range) {
-        rest_range.move_to_next_char();
-    } else {
-        return ParseInlineElementResult {
-            value: InlineElement::ParseError,
-            errors,
-            warnings,
-            rest_range,
-        };
+// `delimiter` で囲まれたインライン要素 (数式、コード、small-caps) をパースする
+fn parse_inline_surrounded_element(
+    src: &Source,
+    delimiters: &Delimiters,
+    delimiter: char,
+    rest_range: InlineRange,
+    make_func: fn(InlineRange) -> InlineElement,
+) -> Option<ParseInlineElementResult> {
+    if !check_at(src, delimiter, &rest_range) || rest_range.is_empty() {
+        return None;
     }
-
-    let url_start_column = rest_range.start_column;
-    loop {
-        match pick_char(src, &rest_range) {
-            Some(')') => {
-                break;
-            }
-            Some(_) => {
-                rest_range.move_to_next_char();
-            }
-            None => {
-                return ParseInlineElementResult {
-                    value: InlineElement::ParseError,
-                    errors,
-                    warnings,
-                    rest_range,
-                }
-            }
-        }
-    }
-    let url_end_column = rest_range.start_column;
-    rest_range.move_to_next_char();
-
-    let url_range = InlineRange {
-        line: rest_range.line,
-        start_column: url_start_column,
-        end_column: url_end_column,
+    let inner_range = InlineRange {
+        start_column: rest_range.start_column + 1,
+        end_column: delimiters.find(delimiter, &rest_range.consume(1))?,
+        ..rest_range
     };
-
-    ParseInlineElementResult {
-        value: InlineElement::Link { text, url_range },
-        errors,
-        warnings,
-        rest_range,
-    }
+    Some(ParseInlineElementResult {
+        value: make_func(inner_range),
+        errors: vec![],
+        warnings: vec![],
+        rest_range: rest_range.consume(inner_range.end_column - rest_range.start_column + 1),
+    })
 }
 
-fn parse_inline_math_element(
-    src: &Vec<Vec<char>>,
-    rest_range: &InlineRange,
+// 閉じられていない区切り文字 1 文字を失敗した範囲にして、その直後から読み進める
+// 本文中の単独の '$' などはよくあるので、エラーではなく警告にしてそのまま出力させる
+fn parse_unclosed_delimiter(
+    src: &Source,
+    rest_range: InlineRange,
+    message: &str,
 ) -> ParseInlineElementResult {
-    let mut rest_range = *rest_range;
-    let errors = vec![];
-    let warnings = vec![];
-    if let Some('$') = pick_char(src, &rest_range) {
-        rest_range.move_to_next_char();
-        let inline_math_start_column = rest_range.start_column;
-        loop {
-            match pick_char(src, &rest_range) {
-                Some('$') => {
-                    let inline_math_range = InlineRange {
-                        line: rest_range.line,
-                        start_column: inline_math_start_column,
-                        end_column: rest_range.start_column,
-                    };
-                    rest_range.move_to_next_char();
-                    return ParseInlineElementResult {
-                        value: InlineElement::Math {
-                            range: inline_math_range,
-                        },
-                        errors,
-                        warnings,
-                        rest_range,
-                    };
-                }
-                Some(_) => {
-                    rest_range.move_to_next_char();
-                }
-                None => {
-                    return ParseInlineElementResult {
-                        value: InlineElement::ParseError,
-                        errors,
-                        warnings,
-                        rest_range,
-                    };
-                }
-            }
-        }
-    } else {
-        ParseInlineElementResult {
-            value: InlineElement::ParseError,
-            errors,
-            warnings,
-            rest_range,
-        }
+    let range = head_char_range(src, &rest_range);
+    let end_of_line = InlineRange {
+        start_column: rest_range.end_column,
+        ..rest_range
+    };
+    let warning = Diagnostic::new("unclosed-inline", end_of_line, message)
+        .with_label(range, format!("'{}' opened here", src.slice(&range)))
+        .with_note("inline elements must be closed on the same line");
+    ParseInlineElementResult {
+        value: InlineElement::ParseError {
+            range,
+            message: message.to_string(),
+        },
+        errors: vec![],
+        warnings: vec![warning],
+        rest_range: rest_range.consume(range.end_column - range.start_column),
     }
 }
 
-fn parse_inline_code_element(
-    src: &Vec<Vec<char>>,
-    rest_range: &InlineRange,
-) -> ParseInlineElementResult {
-    let mut rest_range = *rest_range;
-    let errors = vec![];
-    let warnings = vec![];
-    if let Some('`') = pick_char(src, &rest_range) {
-        rest_range.move_to_next_char();
-        let inline_code_start_column = rest_range.start_column;
-        loop {
-            match pick_char(src, &rest_range) {
-                Some('`') => {
-                    let inline_code_range = InlineRange {
-                        line: rest_range.line,
-                        start_column: inline_code_start_column,
-                        end_column: rest_range.start_column,
-                    };
-                    rest_range.move_to_next_char();
-                    return ParseInlineElementResult {
-                        value: InlineElement::Code {
-                            range: inline_code_range,
-                        },
-                        errors,
-                        warnings,
-                        rest_range,
-                    };
-                }
-                Some(_) => {
-                    rest_range.move_to_next_char();
-                }
-                None => {
-                    return ParseInlineElementResult {
-                        value: InlineElement::ParseError,
-                        errors,
-                        warnings,
-                        rest_range,
-                    };
-                }
-            }
-        }
-    } else {
-        ParseInlineElementResult {
-            value: InlineElement::ParseError,
-            errors,
-            warnings,
-            rest_range,
-        }
+// `range` の先頭の 1 文字を指す range。文字がなければ先頭位置の空の range
+fn head_char_range(src: &Source, range: &InlineRange) -> InlineRange {
+    let len = pick_char(src, range).map_or(0, char::len_utf8);
+    InlineRange {
+        end_column: range.start_column + len,
+        ..*range
     }
 }
 
-fn parse_inline_small_caps_element(
-    src: &Vec<Vec<char>>,
-    rest_range: &InlineRange,
-) -> ParseInlineElementResult {
-    let mut rest_range = *rest_range;
-    let errors = vec![];
-    let warnings = vec![];
-    if let Some('%') = pick_char(src, &rest_range) {
-        rest_range.move_to_next_char();
-        let inline_start_column = rest_range.start_column;
-        loop {
-            match pick_char(src, &rest_range) {
-                Some('%') => {
-                    let inline_range = InlineRange {
-                        line: rest_range.line,
-                        start_column: inline_start_column,
-                        end_column: rest_range.start_column,
-                    };
-                    rest_range.move_to_next_char();
-                    return ParseInlineElementResult {
-                        value: InlineElement::SmallCaps {
-                            range: inline_range,
-                        },
-                        errors,
-                        warnings,
-                        rest_range,
-                    };
-                }
-                Some(_) => {
-                    rest_range.move_to_next_char();
-                }
-                None => {
-                    return ParseInlineElementResult {
-                        value: InlineElement::ParseError,
-                        errors,
-                        warnings,
-                        rest_range,
-                    };
-                }
-            }
-        }
-    } else {
-        ParseInlineElementResult {
-            value: InlineElement::ParseError,
-            errors,
-            warnings,
-            rest_range,
-        }
-    }
+fn pick_char(src: &Source, range: &InlineRange) -> Option<char> {
+    src.pick_char(range)
 }
 
-fn pick_char(src: &Vec<Vec<char>>, range: &InlineRange) -> Option<char> {
-    if let Some(line) = src.iter().nth(range.line) {
-        if let Some(c) = line.iter().nth(range.start_column) {
-            Some(*c)
-        } else {
-            None
-        }
-    } else {
-        None
-    }
+fn move_to_next_char(src: &Source, range: &mut InlineRange) {
+    src.move_to_next_char(range);
 }
 
-fn check_at(src: &Vec<Vec<char>>, expected: char, range: &InlineRange) -> bool {
+fn check_at(src: &Source, expected: char, range: &InlineRange) -> bool {
     if let Some(actual) = pick_char(src, range) {
         expected == actual
     } else {
@@ -1335,17 +1477,10 @@ fn check_at(src: &Vec<Vec<char>>, expected: char, range: &InlineRange) -> bool {
     }
 }
 
-fn starts_with(src: &Vec<Vec<char>>, expected: &str, mut range: InlineRange) -> bool {
-    expected.chars().all(|c| {
-        let res = check_at(src, c, &range);
-        if range.is_empty() {
-            return false;
-        }
-        range.move_to_next_char();
-        res
-    })
+fn starts_with(src: &Source, expected: &str, range: InlineRange) -> bool {
+    src.slice(&range).starts_with(expected)
 }
 
-fn match_(src: &Vec<Vec<char>>, expected: &str, range: InlineRange) -> bool {
-    expected.len() == range.end_column - range.start_column && starts_with(src, expected, range)
+fn match_(src: &Source, expected: &str, range: InlineRange) -> bool {
+    src.slice(&range) == expected
 }
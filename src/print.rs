@@ -1,87 +1,334 @@
 use crate::document::*;
 use crate::katex;
+use crate::lint;
+use crate::output::OutputDir;
 use std::collections::HashMap;
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-pub struct Printer {
-    template_path: PathBuf,
-    output_path: PathBuf,
+/// 数式の描画方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MathMode {
+    /// `npx katex` でビルド時に HTML へ変換する
+    #[default]
+    Katex,
+    /// `\(..\)`, `\[..\]` で囲んで出力し、ブラウザ側の KaTeX auto-render に任せる
+    Client,
 }
 
-impl Printer {
-    pub fn setup(output_path: PathBuf) -> Printer {
-        // 環境変数から設定パスを取得
-        let bokuteki_config_path_string = std::env::var("BOKUTEKI_CONFIG_PATH")
-            .expect("env variable `$BOKUTEKI_CONFIG_PATH` is not defined.");
-        let config_path = PathBuf::from(&bokuteki_config_path_string);
-        let template_path = config_path.join("template");
-
-        // 出力ディレクトリをクリーン
-        if output_path.exists() {
-            fs::remove_dir_all(&output_path).expect("failed to clean output directory..");
+impl std::str::FromStr for MathMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "katex" => Ok(MathMode::Katex),
+            "client" => Ok(MathMode::Client),
+            _ => Err(format!(
+                "unknown math mode `{}` (expected `katex` or `client`)",
+                s
+            )),
         }
-        fs::create_dir(&output_path).expect("failed to create output directory..");
+    }
+}
 
-        assert!(template_path.is_dir());
-        assert!(output_path.is_dir());
+/// HTML 出力のオプション
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    pub math_mode: MathMode,
+}
 
-        // 共通ファイルを配置
-        fs::copy(
-            template_path.join("bokuteki.css"),
-            output_path.join("bokuteki.css"),
-        )
-        .expect("failed to copy bokuteki.css");
-        fs::copy(
-            template_path.join("bokuteki.js"),
-            output_path.join("bokuteki.js"),
-        )
-        .expect("failed to copy bokuteki.js");
-
-        Printer {
-            template_path,
-            output_path,
+/// ページ全体に関わる出力のオプション
+#[derive(Debug, Clone)]
+pub struct PageOptions {
+    /// `<html data-theme="..">` に入る。bokuteki.css は "light" と "dark" に対応する
+    pub theme: String,
+    /// `<html lang="..">` に入る
+    pub language: String,
+    /// 公開先の URL。あれば各ページに canonical リンクを入れる
+    pub base_url: Option<String>,
+}
+
+impl Default for PageOptions {
+    fn default() -> Self {
+        PageOptions {
+            theme: "light".to_string(),
+            language: "ja".to_string(),
+            base_url: None,
         }
     }
+}
 
-    pub fn print(
+/// ページのテンプレート
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub html: String,
+    pub css: String,
+    pub js: String,
+}
+
+impl Template {
+    /// template.html, bokuteki.css, bokuteki.js を含むディレクトリから読む
+    pub fn load(dir: &Path) -> io::Result<Template> {
+        if !dir.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("template directory not found: {}", dir.display()),
+            ));
+        }
+        Ok(Template {
+            html: fs::read_to_string(dir.join("template.html"))?,
+            css: fs::read_to_string(dir.join("bokuteki.css"))?,
+            js: fs::read_to_string(dir.join("bokuteki.js"))?,
+        })
+    }
+
+    /// 本文の HTML をテンプレートに埋め込んでページ全体を作る
+    ///
+    /// `src_path` は根からの相対パスで、css, js へのリンクと canonical リンクに使う。
+    pub fn render_page(
         &self,
-        src: &Vec<Vec<char>>,
         src_path: &Path,
-        block_elements: Vec<BlockElement>,
-        title_content: &str,
-    ) {
-        // 出力する内容を構築
-        let template_content = fs::read_to_string(self.template_path.join("template.html"))
-            .expect("failed to read template.html");
+        title: &str,
+        body: &str,
+        page_options: &PageOptions,
+    ) -> String {
         let relative_to_root = calc_relative_to_root(src_path);
-        let body_content: String = print_block_elements(src, block_elements, 4, true);
         let css_path = relative_to_root.join("bokuteki.css");
         let js_path = relative_to_root.join("bokuteki.js");
-        let html_content = template_content
-            .replace("{title-string}", title_content)
-            .replace("{body-string}", &body_content)
+        let head_content = match &page_options.base_url {
+            Some(base_url) => format!(
+                r#"<link rel="canonical" href="{}/{}">"#,
+                base_url.trim_end_matches('/'),
+                url_path(&html_path_of(src_path))
+            ),
+            None => String::new(),
+        };
+        self.html
+            .replace("{language}", &page_options.language)
+            .replace("{theme}", &page_options.theme)
+            .replace("{head-string}", &head_content)
+            .replace("{title-string}", title)
+            .replace("{body-string}", body)
             .replace("{bokuteki-css-path}", &css_path.display().to_string())
-            .replace("{bokuteki-js-path}", &js_path.display().to_string());
+            .replace("{bokuteki-js-path}", &js_path.display().to_string())
+    }
+
+    /// バイナリに埋め込まれた既定のテンプレート
+    pub fn embedded() -> Template {
+        Template {
+            html: include_str!("../bokuteki-config/template/template.html").to_string(),
+            css: include_str!("../bokuteki-config/template/bokuteki.css").to_string(),
+            js: include_str!("../bokuteki-config/template/bokuteki.js").to_string(),
+        }
+    }
+}
+
+/// ブロック要素列を HTML 断片として出力する
+pub fn render_html(
+    src: &Source,
+    block_elements: &[BlockElement],
+    options: &RenderOptions,
+) -> String {
+    let ctx = Context {
+        src,
+        page_path: Path::new(""),
+        file_path: Path::new(""),
+        options,
+        math: MathBackend::Immediate,
+    };
+    print_block_elements(&ctx, block_elements, 0, true)
+}
+
+/// katex で描画する数式 (TeX, display mode か)
+pub type MathRequest = (String, bool);
+
+/// 先に描画しておいた数式。描画に失敗したものは含まない
+pub type RenderedMath = HashMap<MathRequest, String>;
 
-        // 出力する先を構築
-        let mut html_path = self.output_path.join(src_path);
-        html_path.set_extension("html");
-        fs::create_dir_all(html_path.parent().unwrap()).unwrap();
+/// `requests` の数式を 1 つの katex のプロセスでまとめて描画する。重複したものは 1 度だけ描画する
+pub fn render_math_batch(mut requests: Vec<MathRequest>) -> RenderedMath {
+    requests.sort_unstable();
+    requests.dedup();
+    let Ok(rendered) = katex::render_batch(&requests) else {
+        return RenderedMath::new();
+    };
+    requests
+        .into_iter()
+        .zip(rendered)
+        .filter_map(|(request, rendered)| Some((request, rendered.ok()?)))
+        .collect()
+}
+
+struct Context<'a> {
+    src: &'a Source<'a>,
+    // 出力しているページと、`src` のファイルの根からの相対パス。include したファイルでは異なる
+    page_path: &'a Path,
+    file_path: &'a Path,
+    options: &'a RenderOptions,
+    math: MathBackend<'a>,
+}
+
+// katex での数式の描画の仕方
+#[derive(Clone, Copy)]
+enum MathBackend<'a> {
+    // その場で katex を呼ぶ
+    Immediate,
+    // 描画せずに、必要な数式を集める
+    Collect(&'a Mutex<Vec<MathRequest>>),
+    // 先に描画しておいた結果を使う
+    Prerendered(&'a RenderedMath),
+}
+
+impl Context<'_> {
+    // katex で描画する。失敗したら None
+    fn katex(&self, math_src: String, is_display_mode: bool) -> Option<String> {
+        match self.math {
+            MathBackend::Immediate => katex::render(math_src, is_display_mode).ok(),
+            MathBackend::Collect(requests) => {
+                requests.lock().unwrap().push((math_src, is_display_mode));
+                None
+            }
+            MathBackend::Prerendered(rendered) => {
+                rendered.get(&(math_src, is_display_mode)).cloned()
+            }
+        }
+    }
+}
+
+pub struct Printer {
+    template: Template,
+    output: OutputDir,
+    options: RenderOptions,
+    page_options: PageOptions,
+}
+
+impl Printer {
+    pub fn setup(
+        output_path: PathBuf,
+        template: Template,
+        options: RenderOptions,
+        page_options: PageOptions,
+        force: bool,
+    ) -> io::Result<Printer> {
+        let output = OutputDir::open(output_path, force)?;
+
+        // 共通ファイルを配置
+        output.write(Path::new("bokuteki.css"), &template.css)?;
+        output.write(Path::new("bokuteki.js"), &template.js)?;
+
+        Ok(Printer {
+            template,
+            output,
+            options,
+            page_options,
+        })
+    }
+
+    /// ページを出力するときに katex で描画する数式を集める
+    pub fn math_requests(&self, src: &Source, block_elements: &[BlockElement]) -> Vec<MathRequest> {
+        if self.options.math_mode != MathMode::Katex {
+            return vec![];
+        }
+        let requests = Mutex::new(vec![]);
+        let ctx = Context {
+            src,
+            page_path: Path::new(""),
+            file_path: Path::new(""),
+            options: &self.options,
+            math: MathBackend::Collect(&requests),
+        };
+        print_block_elements(&ctx, block_elements, 4, true);
+        requests.into_inner().unwrap()
+    }
+
+    /// 前回出力したページを今回も残す (パースエラーで出力しなかった場合など)
+    pub fn keep(&self, src_path: &Path) {
+        self.output.keep(&html_path_of(src_path));
+    }
+
+    /// 前回出力したページが出力ディレクトリに残っているか
+    pub fn has_page(&self, src_path: &Path) -> bool {
+        self.output.contains(&html_path_of(src_path))
+    }
+
+    /// ページ以外のファイルを出力ディレクトリに書く
+    pub fn write_file(&self, relative_path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+        self.output.write(relative_path, contents)
+    }
+
+    /// 今回のビルドで出力しなかった古いファイルを片付ける
+    pub fn finish(self) -> io::Result<()> {
+        self.output.finish()
+    }
+
+    pub fn print(
+        &self,
+        src: &Source,
+        src_path: &Path,
+        block_elements: &[BlockElement],
+        title_content: &str,
+        math: &RenderedMath,
+    ) -> io::Result<()> {
+        // 出力する内容を構築
+        let ctx = Context {
+            src,
+            page_path: src_path,
+            file_path: src_path,
+            options: &self.options,
+            math: MathBackend::Prerendered(math),
+        };
+        let body_content: String = print_block_elements(&ctx, block_elements, 4, true);
+        let html_content =
+            self.template
+                .render_page(src_path, title_content, &body_content, &self.page_options);
 
         // 出力
-        use std::io::Write;
-        let mut html_file = fs::File::create(html_path).expect("faild to create html file");
-        html_file
-            .write_all(html_content.as_bytes())
-            .expect("failed to write out html content..");
+        self.output.write(&html_path_of(src_path), html_content)
     }
 }
 
+/// 見出しや定理のタイトルから、ページ中での id を作る。空白は '-' にする。タイトルが空なら None
+///
+/// `[..](foo.bok#id)` のアンカーはこの id を指す。
+pub fn anchor_id(title: &str) -> Option<String> {
+    let id = title.split_whitespace().collect::<Vec<_>>().join("-");
+    (!id.is_empty()).then_some(id)
+}
+
+// `.bok` へのリンクを、出力しているページからの `.html` へのリンクにする
+fn link_href(ctx: &Context, url: &str) -> String {
+    let Some((path, anchor)) = lint::bok_link(url) else {
+        return url.to_string();
+    };
+    let html_path = match lint::resolve_bok_link(ctx.file_path, path) {
+        Some(target) => url_path(&calc_relative_to_root(ctx.page_path).join(html_path_of(&target))),
+        // 根の外を指すものは、書かれたパスの拡張子だけを変える
+        None => format!("{}.html", path.strip_suffix(".bok").unwrap_or(path)),
+    };
+    match anchor {
+        Some(anchor) => format!("{}#{}", html_path, anchor),
+        None => html_path,
+    }
+}
+
+fn html_path_of(src_path: &Path) -> PathBuf {
+    src_path.with_extension("html")
+}
+
+// URL 中で使う '/' 区切りのパス
+fn url_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
 // import に指定されたファイルパスからプロジェクトルートへの相対パスを得る
 // e.g., "foo/bar/baz.bok" から "../../" を得る
 fn calc_relative_to_root(filepath: &Path) -> PathBuf {
-    let depth = filepath.components().collect::<Vec<_>>().len() - 1;
+    let depth = filepath.components().count().saturating_sub(1);
     if depth == 0 {
         return PathBuf::from("./");
     }
@@ -93,25 +340,26 @@ fn calc_relative_to_root(filepath: &Path) -> PathBuf {
 }
 
 fn print_block_elements(
-    src: &Vec<Vec<char>>,
-    block_elements: Vec<BlockElement>,
+    ctx: &Context,
+    block_elements: &[BlockElement],
     indent_depth: usize,
     needs_margin: bool,
 ) -> String {
     block_elements
-        .into_iter()
-        .map(|block_element| print_block_element(src, block_element, indent_depth, needs_margin))
+        .iter()
+        .map(|block_element| print_block_element(ctx, block_element, indent_depth, needs_margin))
         .collect::<Vec<_>>()
         .join("\n")
 }
 
+// 属性は与えた順に出力する
 fn print_html_tag(
     tag_name: &str,
-    attributes: HashMap<&str, &str>,
+    attributes: &[(&str, &str)],
     inner: String,
     indent_depth: usize,
 ) -> String {
-    let indent: String = std::iter::repeat(" ").take(indent_depth).collect();
+    let indent = " ".repeat(indent_depth);
     let mut attributes_str = String::new();
     for (attr_name, value) in attributes {
         attributes_str += &format!(r#" {}="{}""#, attr_name, value);
@@ -123,32 +371,58 @@ fn print_html_tag(
     )
 }
 
+// 属性を設定する。すでにあれば値を置き換え、なければ末尾に足す
+fn set_attribute<'a>(attributes: &mut Vec<(&'a str, &'a str)>, name: &'a str, value: &'a str) {
+    match attributes
+        .iter_mut()
+        .find(|(attr_name, _)| *attr_name == name)
+    {
+        Some(attribute) => attribute.1 = value,
+        None => attributes.push((name, value)),
+    }
+}
+
 fn print_block_element(
-    src: &Vec<Vec<char>>,
-    block_element: BlockElement,
+    ctx: &Context,
+    block_element: &BlockElement,
     indent_depth: usize,
     needs_margin: bool,
 ) -> String {
-    let mut attributes: HashMap<_, _> = if needs_margin {
-        vec![("class", "block")].into_iter().collect()
+    let mut attributes = if needs_margin {
+        vec![("class", "block")]
     } else {
-        HashMap::new()
+        vec![]
     };
     match block_element {
         BlockElement::Heading { level, content } => {
-            let content = print_inline_elements(src, content, indent_depth + 4);
+            let id = anchor_id(&ctx.src.plain_text(content)).map(|id| escape_html(&id));
+            if let Some(id) = &id {
+                set_attribute(&mut attributes, "id", id);
+            }
+            let content = print_inline_elements(ctx, content, indent_depth + 4);
             let tag_name = format!("h{}", level);
-            print_html_tag(&tag_name, attributes, content, indent_depth)
+            print_html_tag(&tag_name, &attributes, content, indent_depth)
         }
         BlockElement::Paragraph { content } => {
-            let content = print_inline_elements(src, content, indent_depth + 4);
-            print_html_tag("p", attributes, content, indent_depth)
+            let content = print_inline_elements(ctx, content, indent_depth + 4);
+            print_html_tag("p", &attributes, content, indent_depth)
         }
-        BlockElement::Code { lines } => {
-            let indent: String = std::iter::repeat(" ").take(indent_depth).collect();
-            let inner = verbatim_block_content(src, &lines);
+        BlockElement::Code {
+            lines,
+            language,
+            excerpt,
+        } => {
+            let indent = " ".repeat(indent_depth);
+            let inner = match excerpt {
+                // ファイルの中身は HTML として書かれたものではないのでエスケープする
+                Some(excerpt) => escape_html(excerpt.text.as_deref().unwrap_or("")),
+                None => verbatim_block_content(ctx.src, lines),
+            };
+            if let Some(language) = language {
+                set_attribute(&mut attributes, "data-language", language);
+            }
             let attributes = attributes
-                .into_iter()
+                .iter()
                 .map(|(name, value)| format!(r#"{}="{}""#, name, value))
                 .collect::<Vec<_>>()
                 .join(" ");
@@ -158,58 +432,100 @@ fn print_block_element(
             )
         }
         BlockElement::Math { lines } => {
-            let content = verbatim_block_content(src, &lines);
-            katex::render(content, true)
+            let content = verbatim_block_content(ctx.src, lines);
+            render_math(ctx, content, true)
         }
         BlockElement::Theorem {
             kind: _kind,
             title,
             content,
         } => {
-            let title = print_inline_elements(src, title, 0);
-            let content = print_block_elements(src, content, indent_depth + 4, false);
-            attributes.insert("class", "math-theorem");
-            attributes.insert("data-title", &title);
-            print_html_tag("div", attributes, content, indent_depth)
+            let id = anchor_id(&ctx.src.plain_text(title)).map(|id| escape_html(&id));
+            set_attribute(&mut attributes, "class", "math-theorem");
+            if let Some(id) = &id {
+                set_attribute(&mut attributes, "id", id);
+            }
+            let title = print_inline_elements(ctx, title, 0);
+            let content = print_block_elements(ctx, content, indent_depth + 4, false);
+            set_attribute(&mut attributes, "data-title", &title);
+            print_html_tag("div", &attributes, content, indent_depth)
         }
         BlockElement::Proof { content } => {
-            let content = print_block_elements(src, content, indent_depth + 4, false);
-            attributes.insert("class", "math-proof");
-            print_html_tag("div", attributes, content, indent_depth)
+            let content = print_block_elements(ctx, content, indent_depth + 4, false);
+            set_attribute(&mut attributes, "class", "math-proof");
+            print_html_tag("div", &attributes, content, indent_depth)
         }
-        BlockElement::Derivation(derivation) => print_derivation(src, derivation),
+        BlockElement::Derivation(derivation) => print_derivation(ctx, derivation),
         BlockElement::List {
             mark_kind: _mark_kind,
             items,
         } => {
             let items = items
-                .into_iter()
+                .iter()
                 .map(|item| {
-                    let top_line = print_inline_elements(src, item.top_line, indent_depth + 8);
-                    let blocks = print_block_elements(src, item.blocks, indent_depth + 8, false);
+                    let top_line = print_inline_elements(ctx, &item.top_line, indent_depth + 8);
+                    let blocks = print_block_elements(ctx, &item.blocks, indent_depth + 8, false);
                     let content = if blocks.is_empty() {
                         top_line
                     } else {
                         format!("{}\n{}", top_line, blocks)
                     };
-                    print_html_tag("li", HashMap::new(), content, indent_depth + 4)
+                    print_html_tag("li", &[], content, indent_depth + 4)
                 })
                 .collect::<Vec<_>>()
                 .join("\n");
 
-            print_html_tag("ul", attributes, items, indent_depth)
+            print_html_tag("ul", &attributes, items, indent_depth)
         }
         BlockElement::Blockquote { inner } => {
-            let inner = print_block_elements(src, inner, indent_depth + 4, true);
-            print_html_tag("blockquote", attributes, inner, indent_depth)
+            let inner = print_block_elements(ctx, inner, indent_depth + 4, true);
+            print_html_tag("blockquote", &attributes, inner, indent_depth)
+        }
+        BlockElement::Include(include) => match &include.content {
+            // 取り込んだブロック要素は、取り込んだファイルの中身を元に出力する
+            Some(content) => {
+                let src = Source::new(&content.file.text);
+                let ctx = Context {
+                    src: &src,
+                    file_path: &content.file.path,
+                    ..*ctx
+                };
+                print_block_elements(&ctx, content.block_elements(), indent_depth, needs_margin)
+            }
+            // 取り込めなかった理由は診断として報告される
+            None => String::new(),
+        },
+        BlockElement::ParseError { lines, message } => {
+            // 失敗した箇所はメッセージとソースをそのまま見せる
+            let indent = " ".repeat(indent_depth + 4);
+            let location = lines
+                .front()
+                .map_or_else(String::new, |line| format!(" (line {})", line.line + 1));
+            let snippet = escape_html(&verbatim_block_content(ctx.src, lines));
+            let content = format!(
+                r#"{indent}<p class="parse-error-message">{}{}</p>
+{indent}<pre class="parse-error-snippet">
+{snippet}</pre>"#,
+                escape_html(message),
+                location
+            );
+            set_attribute(&mut attributes, "class", "parse-error");
+            print_html_tag("div", &attributes, content, indent_depth)
         }
-        BlockElement::ParseError => "parse error..".to_string(),
     }
 }
 
-fn print_derivation(src: &Vec<Vec<char>>, derivation: Derivation) -> String {
-    let (katex_src, inner_elements) = print_derivation_impl(src, derivation, vec![]);
-    let content = katex::render(katex_src, true);
+fn print_derivation(ctx: &Context, derivation: &Derivation) -> String {
+    if ctx.options.math_mode == MathMode::Client {
+        // ブラウザ側で描画する場合は中身の HTML を埋め込めないので、全体を TeX として出力する
+        return render_math(ctx, derivation_to_tex(ctx.src, derivation), true);
+    }
+    let (katex_src, inner_elements) = print_derivation_impl(ctx, derivation, vec![]);
+    let content = match ctx.katex(katex_src, true) {
+        Some(content) => content,
+        // katex で描画できなければブラウザ側での描画に任せる
+        None => return client_math(derivation_to_tex(ctx.src, derivation), true),
+    };
     inner_elements
         .into_iter()
         .enumerate()
@@ -220,8 +536,8 @@ fn print_derivation(src: &Vec<Vec<char>>, derivation: Derivation) -> String {
 }
 
 fn print_derivation_impl(
-    src: &Vec<Vec<char>>,
-    derivation: Derivation,
+    ctx: &Context,
+    derivation: &Derivation,
     mut inner_elements: Vec<String>,
 ) -> (String, Vec<String>) {
     fn make_fresh_mark(count: usize) -> String {
@@ -237,18 +553,15 @@ fn print_derivation_impl(
             let mut premise_katex_srcs = vec![];
             for premise in premises {
                 let (premise_katex_src, inner_elements_) =
-                    print_derivation_impl(src, premise, inner_elements);
+                    print_derivation_impl(ctx, premise, inner_elements);
                 premise_katex_srcs.push(premise_katex_src);
                 inner_elements = inner_elements_;
             }
-            let premises_katex_src = premise_katex_srcs
-                .into_iter()
-                .collect::<Vec<_>>()
-                .join("\\ \\ ");
+            let premises_katex_src = premise_katex_srcs.join("\\ \\ ");
 
             let conclusion_mark = make_fresh_mark(inner_elements.len());
             let conclusion_katex_src = conclusion_mark;
-            inner_elements.push(print_inline_elements(src, conclusion, 0));
+            inner_elements.push(print_inline_elements(ctx, conclusion, 0));
 
             if rule_name.is_empty() {
                 let katex_src =
@@ -258,14 +571,14 @@ fn print_derivation_impl(
             } else {
                 let rule_name_mark = make_fresh_mark(inner_elements.len());
                 let rule_name_katex_src = rule_name_mark;
-                inner_elements.push(print_inline_elements(src, rule_name, 0));
+                inner_elements.push(print_inline_elements(ctx, rule_name, 0));
                 let katex_src =
                     format!("\\dfrac{{{premises_katex_src}}}{{{conclusion_katex_src}}} {rule_name_katex_src}");
                 (katex_src, inner_elements)
             }
         }
         Derivation::Leaf(inline_elements) => {
-            let inner_element = print_inline_elements(src, inline_elements, 0);
+            let inner_element = print_inline_elements(ctx, inline_elements, 0);
             let katex_src = make_fresh_mark(inner_elements.len());
             inner_elements.push(inner_element);
             (katex_src, inner_elements)
@@ -273,80 +586,107 @@ fn print_derivation_impl(
     }
 }
 
+// 導出木全体を 1 つの TeX ソースにする
+fn derivation_to_tex(src: &Source, derivation: &Derivation) -> String {
+    match derivation {
+        Derivation::InferenceRule {
+            premises,
+            conclusion,
+            rule_name,
+        } => {
+            let premises_tex = premises
+                .iter()
+                .map(|premise| derivation_to_tex(src, premise))
+                .collect::<Vec<_>>()
+                .join("\\ \\ ");
+            let conclusion_tex = inline_elements_to_tex(src, conclusion);
+            if rule_name.is_empty() {
+                format!("\\dfrac{{{premises_tex}}}{{{conclusion_tex}}}")
+            } else {
+                let rule_name_tex = inline_elements_to_tex(src, rule_name);
+                format!("\\dfrac{{{premises_tex}}}{{{conclusion_tex}}} {rule_name_tex}")
+            }
+        }
+        Derivation::Leaf(inline_elements) => inline_elements_to_tex(src, inline_elements),
+    }
+}
+
+fn inline_elements_to_tex(src: &Source, inline_elements: &[InlineElement]) -> String {
+    inline_elements
+        .iter()
+        .map(|inline_element| match inline_element {
+            InlineElement::Text { range } => format!("\\text{{{}}}", src.slice(range)),
+            InlineElement::Link { text, .. } => inline_elements_to_tex(src, text),
+            InlineElement::Code { range } => format!("\\texttt{{{}}}", src.slice(range)),
+            InlineElement::Math { range } => format!("{{{}}}", src.slice(range)),
+            InlineElement::SmallCaps { range } => format!("\\textsf{{{}}}", src.slice(range)),
+            InlineElement::ParseError { range, .. } => format!("\\text{{{}}}", src.slice(range)),
+        })
+        .collect()
+}
+
+fn render_math(ctx: &Context, math_src: String, is_display_mode: bool) -> String {
+    match ctx.options.math_mode {
+        MathMode::Katex => ctx
+            .katex(math_src.clone(), is_display_mode)
+            // katex で描画できなければブラウザ側での描画に任せる
+            .unwrap_or_else(|| client_math(math_src, is_display_mode)),
+        MathMode::Client => client_math(math_src, is_display_mode),
+    }
+}
+
+fn client_math(math_src: String, is_display_mode: bool) -> String {
+    if is_display_mode {
+        format!("\\[{}\\]", math_src)
+    } else {
+        format!("\\({}\\)", math_src)
+    }
+}
+
 fn print_inline_elements(
-    src: &Vec<Vec<char>>,
-    inline_elements: Vec<InlineElement>,
+    ctx: &Context,
+    inline_elements: &[InlineElement],
     indent_depth: usize,
 ) -> String {
-    let indent: String = std::iter::repeat(" ").take(indent_depth).collect();
+    let indent = " ".repeat(indent_depth);
     let line = inline_elements
-        .into_iter()
-        .map(|inline_element| print_inline_element(src, inline_element))
+        .iter()
+        .map(|inline_element| print_inline_element(ctx, inline_element))
         .collect::<Vec<_>>()
         .join("");
     format!("{indent}{line}")
 }
 
-fn print_inline_element(src: &Vec<Vec<char>>, inline_element: InlineElement) -> String {
+fn print_inline_element(ctx: &Context, inline_element: &InlineElement) -> String {
     match inline_element {
-        InlineElement::Text { mut range } => {
-            let mut result = String::new();
-            while !range.is_empty() {
-                let c: char = pick_char(src, &range).unwrap();
-                result.push(c);
-                range.move_to_next_char();
-            }
-            result
-        }
-        InlineElement::Link {
-            text,
-            mut url_range,
-        } => {
+        InlineElement::Text { range } => ctx.src.slice(range).to_string(),
+        InlineElement::Link { text, url_range } => {
             let text = text
-                .into_iter()
-                .map(|inline_element| print_inline_element(src, inline_element))
+                .iter()
+                .map(|inline_element| print_inline_element(ctx, inline_element))
                 .collect::<Vec<_>>()
                 .join("");
-            let mut url = String::new();
-            while !url_range.is_empty() {
-                let c = pick_char(src, &url_range).unwrap();
-                url.push(c);
-                url_range.move_to_next_char();
-            }
-            format!("<a href=\"{}\">{}</a>", url, text)
-        }
-        InlineElement::Math { mut range } => {
-            let mut result = String::new();
-            while !range.is_empty() {
-                let c: char = pick_char(src, &range).unwrap();
-                result.push(c);
-                range.move_to_next_char();
-            }
-            katex::render(result, false)
+            let url = ctx.src.slice(url_range);
+            format!("<a href=\"{}\">{}</a>", link_href(ctx, url), text)
         }
-        InlineElement::Code { mut range } => {
-            let mut result = String::new();
-            while !range.is_empty() {
-                let c: char = pick_char(src, &range).unwrap();
-                result.push(c);
-                range.move_to_next_char();
-            }
-            format!("<code>{}</code>", result)
-        }
-        InlineElement::SmallCaps { mut range } => {
-            let mut result = String::new();
-            while !range.is_empty() {
-                let c: char = pick_char(src, &range).unwrap();
-                result.push(c);
-                range.move_to_next_char();
-            }
-            format!(r#"<span class="small-caps">{}</span>"#, result)
+        InlineElement::Math { range } => render_math(ctx, ctx.src.slice(range).to_string(), false),
+        InlineElement::Code { range } => format!("<code>{}</code>", ctx.src.slice(range)),
+        InlineElement::SmallCaps { range } => {
+            format!(
+                r#"<span class="small-caps">{}</span>"#,
+                ctx.src.slice(range)
+            )
         }
-        _ => unimplemented!(),
+        // 失敗した箇所はソースのまま出力する
+        InlineElement::ParseError { range, message } => format!(
+            r#"<span class="parse-error" title="{}">{}</span>"#,
+            escape_html(message),
+            escape_html(ctx.src.slice(range))
+        ),
     }
 }
 
-fn verbatim_block_content(src: &Vec<Vec<char>>, range: &BlockRange) -> String {
+fn verbatim_block_content(src: &Source, range: &BlockRange) -> String {
     range
         .iter()
         .map(|line_range: &InlineRange| verbatim_inline_content(src, line_range))
@@ -354,20 +694,21 @@ fn verbatim_block_content(src: &Vec<Vec<char>>, range: &BlockRange) -> String {
         .join("\n")
 }
 
-fn verbatim_inline_content(src: &Vec<Vec<char>>, range: &InlineRange) -> String {
-    src[range.line][range.start_column..range.end_column]
-        .into_iter()
-        .collect()
+fn verbatim_inline_content<'a>(src: &Source<'a>, range: &InlineRange) -> &'a str {
+    src.slice(range)
 }
 
-fn pick_char(src: &Vec<Vec<char>>, range: &InlineRange) -> Option<char> {
-    if let Some(line) = src.iter().nth(range.line) {
-        if let Some(c) = line.iter().nth(range.start_column) {
-            Some(*c)
-        } else {
-            None
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
         }
-    } else {
-        None
     }
+    escaped
 }
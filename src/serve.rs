@@ -0,0 +1,251 @@
+//! ローカルのプレビューサーバ
+//!
+//! 出力ディレクトリを HTTP で配信し、再ビルドのたびに Server-Sent Events でページにリロードを通知する。
+//! 配信する HTML にはリロードを受け取るスクリプトを注入する。
+
+use crate::build::{BuildConfig, BuildReport};
+use crate::watch;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+/// リロードを通知するイベントのパス
+pub const EVENTS_PATH: &str = "/__bokuteki/events";
+/// ライブリロードのクライアントのパス
+pub const LIVERELOAD_PATH: &str = "/__bokuteki/livereload.js";
+
+const LIVERELOAD_JS: &str = include_str!("../bokuteki-config/serve/livereload.js");
+
+// リクエストの先頭部分の上限
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// `config` でビルドしてから変更を監視し、出力ディレクトリを `listener` で配信する
+///
+/// ビルドするたびに `on_build` を呼び、開いているページにリロードを通知する。
+pub async fn serve(
+    config: BuildConfig,
+    listener: TcpListener,
+    mut on_build: impl FnMut(io::Result<&BuildReport>) + Send + 'static,
+) -> io::Result<()> {
+    let (sender, _) = broadcast::channel(16);
+
+    let output_dir = config.output_dir.clone();
+    let reload_sender = sender.clone();
+    // 監視は戻らないので、ランタイムの終了を妨げないよう専用のスレッドで行う
+    std::thread::spawn(move || {
+        // テンプレートの css, js が変わったときはどのページもリロードが必要
+        let watched_output_dir = config.output_dir.clone();
+        let mut assets = read_assets(&watched_output_dir);
+        watch::watch(&config, move |result| {
+            let current_assets = read_assets(&watched_output_dir);
+            let full = current_assets != assets;
+            assets = current_assets;
+            // 受け取るページがなければ送れないが、それで構わない
+            // `on_build` のあとに接続したページに、前のビルドの通知が届かないよう先に送る
+            let _ = reload_sender.send(serde_json::json!({ "full": full }).to_string());
+            on_build(result);
+        })
+    });
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let output_dir = output_dir.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, &output_dir, &sender).await {
+                log::debug!("connection closed: {}", err);
+            }
+        });
+    }
+}
+
+fn read_assets(output_dir: &Path) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+    (
+        fs::read(output_dir.join("bokuteki.css")).ok(),
+        fs::read(output_dir.join("bokuteki.js")).ok(),
+    )
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    output_dir: &Path,
+    sender: &broadcast::Sender<String>,
+) -> io::Result<()> {
+    let head = read_request_head(&mut stream).await?;
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("");
+    let target = request_line.next().unwrap_or("");
+
+    if method != "GET" && method != "HEAD" {
+        return respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"",
+            method,
+        )
+        .await;
+    }
+    let path = target.split(['?', '#']).next().unwrap_or("");
+    if path == EVENTS_PATH {
+        // 通知を受け取るのはイベントの接続だけ
+        return send_events(stream, sender.subscribe()).await;
+    }
+    if path == LIVERELOAD_PATH {
+        let content_type = "text/javascript; charset=utf-8";
+        return respond(
+            &mut stream,
+            "200 OK",
+            content_type,
+            LIVERELOAD_JS.as_bytes(),
+            method,
+        )
+        .await;
+    }
+
+    match resolve_path(output_dir, path)
+        .and_then(|path| fs::read(&path).ok().map(|body| (path, body)))
+    {
+        Some((path, body)) => {
+            let content_type = content_type_of(&path);
+            let body = if content_type.starts_with("text/html") {
+                inject_livereload(&String::from_utf8_lossy(&body)).into_bytes()
+            } else {
+                body
+            };
+            respond(&mut stream, "200 OK", content_type, &body, method).await
+        }
+        None => {
+            let body = format!("not found: {}", path);
+            respond(
+                &mut stream,
+                "404 Not Found",
+                "text/plain; charset=utf-8",
+                body.as_bytes(),
+                method,
+            )
+            .await
+        }
+    }
+}
+
+async fn read_request_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut head = vec![];
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too large",
+            ));
+        }
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+    method: &str,
+) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    if method != "HEAD" {
+        stream.write_all(body).await?;
+    }
+    stream.shutdown().await
+}
+
+async fn send_events(
+    mut stream: TcpStream,
+    mut receiver: broadcast::Receiver<String>,
+) -> io::Result<()> {
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n: connected\n\n",
+        )
+        .await?;
+    loop {
+        let data = match receiver.recv().await {
+            Ok(data) => data,
+            // 取りこぼしても次の通知でリロードすれば足りる
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        stream
+            .write_all(format!("event: reload\ndata: {}\n\n", data).as_bytes())
+            .await?;
+    }
+}
+
+// URL のパスを出力ディレクトリ内のファイルに対応させる。ディレクトリなら index.html を返す
+fn resolve_path(output_dir: &Path, url_path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(url_path)?;
+    let relative = Path::new(decoded.trim_start_matches('/'));
+    // 出力ディレクトリの外は配信しない
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    let path = output_dir.join(relative);
+    if path.is_dir() {
+        Some(path.join("index.html"))
+    } else {
+        Some(path)
+    }
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn content_type_of(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+// `</body>` の直前にライブリロードのクライアントを読み込む script を入れる
+fn inject_livereload(html: &str) -> String {
+    let script = format!(r#"<script src="{}"></script>"#, LIVERELOAD_PATH);
+    match html.rfind("</body>") {
+        Some(idx) => format!("{}{}\n{}", &html[..idx], script, &html[idx..]),
+        None => format!("{}{}\n", html, script),
+    }
+}
@@ -2,9 +2,9 @@ use crate::document::*;
 use log::debug;
 
 #[allow(dead_code)]
-pub fn debug_at(src: &Vec<Vec<char>>, range: &InlineRange, msg: &str) {
-    if let Some(line) = src.iter().nth(range.line) {
-        let content: String = line[range.start_column..range.end_column].iter().collect();
+pub fn debug_at(src: &Source, range: &InlineRange, msg: &str) {
+    if range.line < src.line_count() {
+        let content = src.slice(range);
         debug!(
             "{} at ({}:{}-{}:{}) | {}",
             msg, range.line, range.start_column, range.line, range.end_column, content
@@ -13,3 +13,36 @@ pub fn debug_at(src: &Vec<Vec<char>>, range: &InlineRange, msg: &str) {
         debug!("out of source : line {}", range.line);
     }
 }
+
+/// `items` の各要素に `f` を適用した結果を、スレッドプールで並行に計算する。結果の順序は `items` の順序と同じ
+pub fn parallel_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    let workers = std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(items.len());
+    if workers <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(idx) else {
+                    break;
+                };
+                let result = f(item);
+                results.lock().unwrap()[idx] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every item is processed"))
+        .collect()
+}
@@ -0,0 +1,640 @@
+//! `Document` の木を走査するための trait
+//!
+//! `Visit` は共有参照で、`VisitMut` は可変参照で木をたどる。ノードを消したり足したりするには、
+//! 木を所有権ごと受け取って作り直す `Fold` を使う。
+//! 各 `visit_*` メソッドのデフォルト実装は対応する `walk_*` 関数を呼んで子ノードを訪問するので、
+//! 興味のあるノードの `visit_*` だけを上書きし、必要なら中で `walk_*` を呼べばよい。
+//!
+//! ```
+//! use bokuteki::document::InlineElement;
+//! use bokuteki::visit::{self, NodePath, Visit};
+//!
+//! struct MathCounter(usize);
+//! impl Visit for MathCounter {
+//!     fn visit_inline_element(&mut self, inline_element: &InlineElement, path: &mut NodePath) {
+//!         if let InlineElement::Math { .. } = inline_element {
+//!             self.0 += 1;
+//!         }
+//!         visit::walk_inline_element(self, inline_element, path);
+//!     }
+//! }
+//!
+//! let parsed = bokuteki::parse("$x$ and $y$\n\n- [$z$](https://example.com)");
+//! let mut counter = MathCounter(0);
+//! counter.visit_document(&parsed.document);
+//! assert_eq!(counter.0, 3);
+//! ```
+
+use crate::document::*;
+
+/// ノードの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Heading,
+    Paragraph,
+    Code,
+    Math,
+    Theorem,
+    Proof,
+    Derivation,
+    List,
+    ListItem,
+    Blockquote,
+    Include,
+    BlockParseError,
+    InferenceRule,
+    Leaf,
+    Text,
+    Link,
+    InlineCode,
+    InlineMath,
+    SmallCaps,
+    InlineParseError,
+}
+
+impl NodeKind {
+    pub fn of_block_element(block_element: &BlockElement) -> Self {
+        match block_element {
+            BlockElement::Heading { .. } => NodeKind::Heading,
+            BlockElement::Paragraph { .. } => NodeKind::Paragraph,
+            BlockElement::Code { .. } => NodeKind::Code,
+            BlockElement::Math { .. } => NodeKind::Math,
+            BlockElement::Theorem { .. } => NodeKind::Theorem,
+            BlockElement::Proof { .. } => NodeKind::Proof,
+            BlockElement::Derivation(_) => NodeKind::Derivation,
+            BlockElement::List { .. } => NodeKind::List,
+            BlockElement::Blockquote { .. } => NodeKind::Blockquote,
+            BlockElement::Include(_) => NodeKind::Include,
+            BlockElement::ParseError { .. } => NodeKind::BlockParseError,
+        }
+    }
+
+    pub fn of_derivation(derivation: &Derivation) -> Self {
+        match derivation {
+            Derivation::InferenceRule { .. } => NodeKind::InferenceRule,
+            Derivation::Leaf(_) => NodeKind::Leaf,
+        }
+    }
+
+    pub fn of_inline_element(inline_element: &InlineElement) -> Self {
+        match inline_element {
+            InlineElement::Text { .. } => NodeKind::Text,
+            InlineElement::Link { .. } => NodeKind::Link,
+            InlineElement::Code { .. } => NodeKind::InlineCode,
+            InlineElement::Math { .. } => NodeKind::InlineMath,
+            InlineElement::SmallCaps { .. } => NodeKind::SmallCaps,
+            InlineElement::ParseError { .. } => NodeKind::InlineParseError,
+        }
+    }
+}
+
+/// 親ノードの中で子ノードが入っているフィールド。名前は `document` モジュールの型のフィールドに対応する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// `Document::block_elements`
+    BlockElements,
+    /// 見出し・段落・定理・証明の `content`
+    Content,
+    /// 定理の `title`
+    Title,
+    /// リストの `items`
+    Items,
+    /// 引用の `inner`
+    Inner,
+    /// `BlockElement::Derivation` の推論図
+    Derivation,
+    /// リストの項目の `top_line`
+    TopLine,
+    /// リストの項目の `blocks`
+    Blocks,
+    /// 推論規則の `premises`
+    Premises,
+    /// 推論規則の `conclusion`
+    Conclusion,
+    /// 推論規則の `rule_name`
+    RuleName,
+    /// `Derivation::Leaf` の中身
+    Leaf,
+    /// リンクの `text`
+    Text,
+}
+
+/// 根から訪問中のノードまでの経路の 1 段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathSegment {
+    pub kind: NodeKind,
+    /// 親ノードのどのフィールドに入っているか
+    pub field: Field,
+    /// `field` の列の中での添字
+    pub index: usize,
+}
+
+/// 根から訪問中のノードまでの経路。最後の要素が訪問中のノード自身を表す
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodePath {
+    segments: Vec<PathSegment>,
+}
+
+impl NodePath {
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    pub fn current(&self) -> Option<&PathSegment> {
+        self.segments.last()
+    }
+
+    pub fn parent(&self) -> Option<&PathSegment> {
+        self.segments.iter().rev().nth(1)
+    }
+
+    /// 指定した種類のノードの内側にいるか
+    pub fn is_inside(&self, kind: NodeKind) -> bool {
+        self.segments
+            .iter()
+            .rev()
+            .skip(1)
+            .any(|segment| segment.kind == kind)
+    }
+
+    fn push(&mut self, kind: NodeKind, field: Field, index: usize) {
+        self.segments.push(PathSegment { kind, field, index });
+    }
+
+    fn pop(&mut self) {
+        self.segments.pop();
+    }
+}
+
+pub trait Visit {
+    fn visit_document(&mut self, document: &Document) {
+        walk_document(self, document);
+    }
+
+    fn visit_block_element(&mut self, block_element: &BlockElement, path: &mut NodePath) {
+        walk_block_element(self, block_element, path);
+    }
+
+    fn visit_list_item(&mut self, list_item: &ListItem, path: &mut NodePath) {
+        walk_list_item(self, list_item, path);
+    }
+
+    fn visit_derivation(&mut self, derivation: &Derivation, path: &mut NodePath) {
+        walk_derivation(self, derivation, path);
+    }
+
+    fn visit_inline_element(&mut self, inline_element: &InlineElement, path: &mut NodePath) {
+        walk_inline_element(self, inline_element, path);
+    }
+}
+
+pub fn walk_document<V: Visit + ?Sized>(visitor: &mut V, document: &Document) {
+    walk_block_elements(
+        visitor,
+        &document.block_elements,
+        Field::BlockElements,
+        &mut NodePath::default(),
+    );
+}
+
+pub fn walk_block_elements<V: Visit + ?Sized>(
+    visitor: &mut V,
+    block_elements: &[BlockElement],
+    field: Field,
+    path: &mut NodePath,
+) {
+    for (index, block_element) in block_elements.iter().enumerate() {
+        path.push(NodeKind::of_block_element(block_element), field, index);
+        visitor.visit_block_element(block_element, path);
+        path.pop();
+    }
+}
+
+pub fn walk_block_element<V: Visit + ?Sized>(
+    visitor: &mut V,
+    block_element: &BlockElement,
+    path: &mut NodePath,
+) {
+    match block_element {
+        BlockElement::Heading { content, .. } | BlockElement::Paragraph { content } => {
+            walk_inline_elements(visitor, content, Field::Content, path);
+        }
+        // 取り込んだブロック要素は別のファイルの中身を指すので辿らない
+        BlockElement::Code { .. }
+        | BlockElement::Math { .. }
+        | BlockElement::Include(_)
+        | BlockElement::ParseError { .. } => {}
+        BlockElement::Theorem { title, content, .. } => {
+            walk_inline_elements(visitor, title, Field::Title, path);
+            walk_block_elements(visitor, content, Field::Content, path);
+        }
+        BlockElement::Proof { content } => {
+            walk_block_elements(visitor, content, Field::Content, path)
+        }
+        BlockElement::Derivation(derivation) => {
+            path.push(NodeKind::of_derivation(derivation), Field::Derivation, 0);
+            visitor.visit_derivation(derivation, path);
+            path.pop();
+        }
+        BlockElement::List { items, .. } => {
+            for (index, item) in items.iter().enumerate() {
+                path.push(NodeKind::ListItem, Field::Items, index);
+                visitor.visit_list_item(item, path);
+                path.pop();
+            }
+        }
+        BlockElement::Blockquote { inner } => {
+            walk_block_elements(visitor, inner, Field::Inner, path)
+        }
+    }
+}
+
+pub fn walk_list_item<V: Visit + ?Sized>(
+    visitor: &mut V,
+    list_item: &ListItem,
+    path: &mut NodePath,
+) {
+    walk_inline_elements(visitor, &list_item.top_line, Field::TopLine, path);
+    walk_block_elements(visitor, &list_item.blocks, Field::Blocks, path);
+}
+
+pub fn walk_derivation<V: Visit + ?Sized>(
+    visitor: &mut V,
+    derivation: &Derivation,
+    path: &mut NodePath,
+) {
+    match derivation {
+        Derivation::InferenceRule {
+            premises,
+            conclusion,
+            rule_name,
+        } => {
+            for (index, premise) in premises.iter().enumerate() {
+                path.push(NodeKind::of_derivation(premise), Field::Premises, index);
+                visitor.visit_derivation(premise, path);
+                path.pop();
+            }
+            walk_inline_elements(visitor, conclusion, Field::Conclusion, path);
+            walk_inline_elements(visitor, rule_name, Field::RuleName, path);
+        }
+        Derivation::Leaf(inline_elements) => {
+            walk_inline_elements(visitor, inline_elements, Field::Leaf, path)
+        }
+    }
+}
+
+pub fn walk_inline_elements<V: Visit + ?Sized>(
+    visitor: &mut V,
+    inline_elements: &[InlineElement],
+    field: Field,
+    path: &mut NodePath,
+) {
+    for (index, inline_element) in inline_elements.iter().enumerate() {
+        path.push(NodeKind::of_inline_element(inline_element), field, index);
+        visitor.visit_inline_element(inline_element, path);
+        path.pop();
+    }
+}
+
+pub fn walk_inline_element<V: Visit + ?Sized>(
+    visitor: &mut V,
+    inline_element: &InlineElement,
+    path: &mut NodePath,
+) {
+    if let InlineElement::Link { text, .. } = inline_element {
+        walk_inline_elements(visitor, text, Field::Text, path);
+    }
+}
+
+/// `Visit` の可変参照版。ノードを書き換えたり置き換えたりできる
+pub trait VisitMut {
+    fn visit_document_mut(&mut self, document: &mut Document) {
+        walk_document_mut(self, document);
+    }
+
+    fn visit_block_element_mut(&mut self, block_element: &mut BlockElement, path: &mut NodePath) {
+        walk_block_element_mut(self, block_element, path);
+    }
+
+    fn visit_list_item_mut(&mut self, list_item: &mut ListItem, path: &mut NodePath) {
+        walk_list_item_mut(self, list_item, path);
+    }
+
+    fn visit_derivation_mut(&mut self, derivation: &mut Derivation, path: &mut NodePath) {
+        walk_derivation_mut(self, derivation, path);
+    }
+
+    fn visit_inline_element_mut(
+        &mut self,
+        inline_element: &mut InlineElement,
+        path: &mut NodePath,
+    ) {
+        walk_inline_element_mut(self, inline_element, path);
+    }
+}
+
+pub fn walk_document_mut<V: VisitMut + ?Sized>(visitor: &mut V, document: &mut Document) {
+    walk_block_elements_mut(
+        visitor,
+        &mut document.block_elements,
+        Field::BlockElements,
+        &mut NodePath::default(),
+    );
+}
+
+pub fn walk_block_elements_mut<V: VisitMut + ?Sized>(
+    visitor: &mut V,
+    block_elements: &mut [BlockElement],
+    field: Field,
+    path: &mut NodePath,
+) {
+    for (index, block_element) in block_elements.iter_mut().enumerate() {
+        path.push(NodeKind::of_block_element(block_element), field, index);
+        visitor.visit_block_element_mut(block_element, path);
+        path.pop();
+    }
+}
+
+pub fn walk_block_element_mut<V: VisitMut + ?Sized>(
+    visitor: &mut V,
+    block_element: &mut BlockElement,
+    path: &mut NodePath,
+) {
+    match block_element {
+        BlockElement::Heading { content, .. } | BlockElement::Paragraph { content } => {
+            walk_inline_elements_mut(visitor, content, Field::Content, path);
+        }
+        BlockElement::Code { .. }
+        | BlockElement::Math { .. }
+        | BlockElement::Include(_)
+        | BlockElement::ParseError { .. } => {}
+        BlockElement::Theorem { title, content, .. } => {
+            walk_inline_elements_mut(visitor, title, Field::Title, path);
+            walk_block_elements_mut(visitor, content, Field::Content, path);
+        }
+        BlockElement::Proof { content } => {
+            walk_block_elements_mut(visitor, content, Field::Content, path)
+        }
+        BlockElement::Derivation(derivation) => {
+            path.push(NodeKind::of_derivation(derivation), Field::Derivation, 0);
+            visitor.visit_derivation_mut(derivation, path);
+            path.pop();
+        }
+        BlockElement::List { items, .. } => {
+            for (index, item) in items.iter_mut().enumerate() {
+                path.push(NodeKind::ListItem, Field::Items, index);
+                visitor.visit_list_item_mut(item, path);
+                path.pop();
+            }
+        }
+        BlockElement::Blockquote { inner } => {
+            walk_block_elements_mut(visitor, inner, Field::Inner, path)
+        }
+    }
+}
+
+pub fn walk_list_item_mut<V: VisitMut + ?Sized>(
+    visitor: &mut V,
+    list_item: &mut ListItem,
+    path: &mut NodePath,
+) {
+    walk_inline_elements_mut(visitor, &mut list_item.top_line, Field::TopLine, path);
+    walk_block_elements_mut(visitor, &mut list_item.blocks, Field::Blocks, path);
+}
+
+pub fn walk_derivation_mut<V: VisitMut + ?Sized>(
+    visitor: &mut V,
+    derivation: &mut Derivation,
+    path: &mut NodePath,
+) {
+    match derivation {
+        Derivation::InferenceRule {
+            premises,
+            conclusion,
+            rule_name,
+        } => {
+            for (index, premise) in premises.iter_mut().enumerate() {
+                path.push(NodeKind::of_derivation(premise), Field::Premises, index);
+                visitor.visit_derivation_mut(premise, path);
+                path.pop();
+            }
+            walk_inline_elements_mut(visitor, conclusion, Field::Conclusion, path);
+            walk_inline_elements_mut(visitor, rule_name, Field::RuleName, path);
+        }
+        Derivation::Leaf(inline_elements) => {
+            walk_inline_elements_mut(visitor, inline_elements, Field::Leaf, path)
+        }
+    }
+}
+
+pub fn walk_inline_elements_mut<V: VisitMut + ?Sized>(
+    visitor: &mut V,
+    inline_elements: &mut [InlineElement],
+    field: Field,
+    path: &mut NodePath,
+) {
+    for (index, inline_element) in inline_elements.iter_mut().enumerate() {
+        path.push(NodeKind::of_inline_element(inline_element), field, index);
+        visitor.visit_inline_element_mut(inline_element, path);
+        path.pop();
+    }
+}
+
+pub fn walk_inline_element_mut<V: VisitMut + ?Sized>(
+    visitor: &mut V,
+    inline_element: &mut InlineElement,
+    path: &mut NodePath,
+) {
+    if let InlineElement::Link { text, .. } = inline_element {
+        walk_inline_elements_mut(visitor, text, Field::Text, path);
+    }
+}
+
+/// 木を所有権ごと受け取って作り直す trait。ノードを消したり、前後にノードを足したりできる
+///
+/// 列に入るノードの `fold_*` は置き換え後のノードの列を返す。空の列を返せばノードを消し、
+/// 複数返せばその位置に挿入する。経路の添字は置き換える前の列での位置を表す。
+pub trait Fold {
+    fn fold_document(&mut self, document: Document) -> Document {
+        walk_document_fold(self, document)
+    }
+
+    fn fold_block_element(
+        &mut self,
+        block_element: BlockElement,
+        path: &mut NodePath,
+    ) -> Vec<BlockElement> {
+        walk_block_element_fold(self, block_element, path)
+    }
+
+    fn fold_list_item(&mut self, list_item: ListItem, path: &mut NodePath) -> Vec<ListItem> {
+        vec![walk_list_item_fold(self, list_item, path)]
+    }
+
+    /// `BlockElement::Derivation` の推論図を複数に置き換えた場合は、それぞれを推論図のブロックにする
+    fn fold_derivation(&mut self, derivation: Derivation, path: &mut NodePath) -> Vec<Derivation> {
+        vec![walk_derivation_fold(self, derivation, path)]
+    }
+
+    fn fold_inline_element(
+        &mut self,
+        inline_element: InlineElement,
+        path: &mut NodePath,
+    ) -> Vec<InlineElement> {
+        vec![walk_inline_element_fold(self, inline_element, path)]
+    }
+}
+
+pub fn walk_document_fold<F: Fold + ?Sized>(folder: &mut F, document: Document) -> Document {
+    Document {
+        block_elements: walk_block_elements_fold(
+            folder,
+            document.block_elements,
+            Field::BlockElements,
+            &mut NodePath::default(),
+        ),
+        ..document
+    }
+}
+
+pub fn walk_block_elements_fold<F: Fold + ?Sized>(
+    folder: &mut F,
+    block_elements: Vec<BlockElement>,
+    field: Field,
+    path: &mut NodePath,
+) -> Vec<BlockElement> {
+    let mut folded = Vec::with_capacity(block_elements.len());
+    for (index, block_element) in block_elements.into_iter().enumerate() {
+        path.push(NodeKind::of_block_element(&block_element), field, index);
+        folded.append(&mut folder.fold_block_element(block_element, path));
+        path.pop();
+    }
+    folded
+}
+
+/// 子ノードを作り直す。`BlockElement::Derivation` は推論図を置き換えた数だけのブロックになるので、列を返す
+pub fn walk_block_element_fold<F: Fold + ?Sized>(
+    folder: &mut F,
+    block_element: BlockElement,
+    path: &mut NodePath,
+) -> Vec<BlockElement> {
+    let block_element = match block_element {
+        BlockElement::Heading { level, content } => BlockElement::Heading {
+            level,
+            content: walk_inline_elements_fold(folder, content, Field::Content, path),
+        },
+        BlockElement::Paragraph { content } => BlockElement::Paragraph {
+            content: walk_inline_elements_fold(folder, content, Field::Content, path),
+        },
+        BlockElement::Code { .. }
+        | BlockElement::Math { .. }
+        | BlockElement::Include(_)
+        | BlockElement::ParseError { .. } => block_element,
+        BlockElement::Theorem {
+            kind,
+            title,
+            content,
+        } => BlockElement::Theorem {
+            kind,
+            title: walk_inline_elements_fold(folder, title, Field::Title, path),
+            content: walk_block_elements_fold(folder, content, Field::Content, path),
+        },
+        BlockElement::Proof { content } => BlockElement::Proof {
+            content: walk_block_elements_fold(folder, content, Field::Content, path),
+        },
+        BlockElement::Derivation(derivation) => {
+            path.push(NodeKind::of_derivation(&derivation), Field::Derivation, 0);
+            let folded = folder.fold_derivation(derivation, path);
+            path.pop();
+            return folded.into_iter().map(BlockElement::Derivation).collect();
+        }
+        BlockElement::List { mark_kind, items } => {
+            let mut folded = Vec::with_capacity(items.len());
+            for (index, item) in items.into_iter().enumerate() {
+                path.push(NodeKind::ListItem, Field::Items, index);
+                folded.append(&mut folder.fold_list_item(item, path));
+                path.pop();
+            }
+            BlockElement::List {
+                mark_kind,
+                items: folded,
+            }
+        }
+        BlockElement::Blockquote { inner } => BlockElement::Blockquote {
+            inner: walk_block_elements_fold(folder, inner, Field::Inner, path),
+        },
+    };
+    vec![block_element]
+}
+
+pub fn walk_list_item_fold<F: Fold + ?Sized>(
+    folder: &mut F,
+    list_item: ListItem,
+    path: &mut NodePath,
+) -> ListItem {
+    ListItem {
+        top_line: walk_inline_elements_fold(folder, list_item.top_line, Field::TopLine, path),
+        blocks: walk_block_elements_fold(folder, list_item.blocks, Field::Blocks, path),
+    }
+}
+
+pub fn walk_derivation_fold<F: Fold + ?Sized>(
+    folder: &mut F,
+    derivation: Derivation,
+    path: &mut NodePath,
+) -> Derivation {
+    match derivation {
+        Derivation::InferenceRule {
+            premises,
+            conclusion,
+            rule_name,
+        } => {
+            let mut folded = Vec::with_capacity(premises.len());
+            for (index, premise) in premises.into_iter().enumerate() {
+                path.push(NodeKind::of_derivation(&premise), Field::Premises, index);
+                folded.append(&mut folder.fold_derivation(premise, path));
+                path.pop();
+            }
+            Derivation::InferenceRule {
+                premises: folded,
+                conclusion: walk_inline_elements_fold(folder, conclusion, Field::Conclusion, path),
+                rule_name: walk_inline_elements_fold(folder, rule_name, Field::RuleName, path),
+            }
+        }
+        Derivation::Leaf(inline_elements) => Derivation::Leaf(walk_inline_elements_fold(
+            folder,
+            inline_elements,
+            Field::Leaf,
+            path,
+        )),
+    }
+}
+
+pub fn walk_inline_elements_fold<F: Fold + ?Sized>(
+    folder: &mut F,
+    inline_elements: Vec<InlineElement>,
+    field: Field,
+    path: &mut NodePath,
+) -> Vec<InlineElement> {
+    let mut folded = Vec::with_capacity(inline_elements.len());
+    for (index, inline_element) in inline_elements.into_iter().enumerate() {
+        path.push(NodeKind::of_inline_element(&inline_element), field, index);
+        folded.append(&mut folder.fold_inline_element(inline_element, path));
+        path.pop();
+    }
+    folded
+}
+
+pub fn walk_inline_element_fold<F: Fold + ?Sized>(
+    folder: &mut F,
+    inline_element: InlineElement,
+    path: &mut NodePath,
+) -> InlineElement {
+    match inline_element {
+        InlineElement::Link { text, url_range } => InlineElement::Link {
+            text: walk_inline_elements_fold(folder, text, Field::Text, path),
+            url_range,
+        },
+        _ => inline_element,
+    }
+}
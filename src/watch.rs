@@ -0,0 +1,141 @@
+//! ファイルの変更を監視して再ビルドする
+//!
+//! 監視には外部のクレートを使わず、更新時刻を一定間隔で調べる。
+
+use crate::build::{build_with_cache, BuildCache, BuildConfig, BuildReport};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// 更新時刻を調べる間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// テンプレートとして監視するファイル
+const TEMPLATE_FILES: [&str; 3] = ["template.html", "bokuteki.css", "bokuteki.js"];
+
+/// 監視しているパスの状態。なければ None
+type Snapshot = BTreeMap<PathBuf, Option<Stamp>>;
+
+/// ファイルは更新時刻で、ディレクトリは中にある名前で変更を調べる
+///
+/// ディレクトリの更新時刻は出力ディレクトリを作ったときにも変わるので使わず、
+/// 出力ディレクトリ自身も名前に含めない。
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stamp {
+    File(Option<SystemTime>),
+    Dir(BTreeSet<OsString>),
+}
+
+/// ビルドしてから、import されているファイルとテンプレートを監視し、変更があるたびに再ビルドする
+///
+/// 変更のあった `.bok` だけを作り直し、テンプレートが変わったときはすべて作り直す。
+/// ビルドするたびに `on_build` を呼ぶ。戻らない。
+pub fn watch(config: &BuildConfig, mut on_build: impl FnMut(io::Result<&BuildReport>)) -> ! {
+    let mut cache = BuildCache::default();
+    let output_dir = std::path::absolute(&config.output_dir).unwrap_or_default();
+    let mut template = template_snapshot(config, &output_dir);
+    let mut sources = rebuild(config, &mut cache, Snapshot::new(), &mut on_build);
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let current_template = template_snapshot(config, &output_dir);
+        if current_template != template {
+            template = current_template;
+            cache.clear();
+        } else if sources
+            .iter()
+            .all(|(path, stamp)| stamp_of(path, &output_dir) == *stamp)
+        {
+            continue;
+        }
+        sources = rebuild(config, &mut cache, sources, &mut on_build);
+    }
+}
+
+// ビルドして、次に監視するソースファイルの更新時刻を返す
+fn rebuild(
+    config: &BuildConfig,
+    cache: &mut BuildCache,
+    sources: Snapshot,
+    on_build: &mut impl FnMut(io::Result<&BuildReport>),
+) -> Snapshot {
+    // ビルド中の変更を取りこぼさないよう、監視していたものの状態はビルドの前に調べる
+    let root_path = &config.root;
+    let output_dir = std::path::absolute(&config.output_dir).unwrap_or_default();
+    let before = snapshot(
+        sources.into_keys().chain(config.entries.iter().cloned()),
+        &output_dir,
+    );
+    match build_with_cache(config, cache) {
+        Ok(report) => {
+            on_build(Ok(&report));
+            // 新しく import されたファイルや、見つからなかったファイルも監視する
+            // import 先が新しく作られたときに気づけるよう、import 先を探すディレクトリも監視する
+            report
+                .files
+                .iter()
+                .map(|file| file.path.as_path())
+                .chain(report.missing_files.iter().map(PathBuf::as_path))
+                .chain(report.excerpt_files.iter().map(PathBuf::as_path))
+                .chain(
+                    report
+                        .files
+                        .iter()
+                        .map(|file| file.path.parent().unwrap_or(Path::new(""))),
+                )
+                .chain(config.search_paths.iter().map(PathBuf::as_path))
+                .map(|path| root_path.join(path))
+                .map(|path| {
+                    let stamp = match before.get(&path) {
+                        Some(stamp) => stamp.clone(),
+                        None => stamp_of(&path, &output_dir),
+                    };
+                    (path, stamp)
+                })
+                .collect()
+        }
+        Err(err) => {
+            on_build(Err(err));
+            // 失敗したときは起点のファイルの変更を待つ
+            before
+        }
+    }
+}
+
+fn template_snapshot(config: &BuildConfig, output_dir: &Path) -> Snapshot {
+    match &config.template_dir {
+        Some(template_dir) => snapshot(
+            TEMPLATE_FILES.iter().map(|file| template_dir.join(file)),
+            output_dir,
+        ),
+        None => Snapshot::new(),
+    }
+}
+
+fn snapshot(paths: impl Iterator<Item = PathBuf>, output_dir: &Path) -> Snapshot {
+    paths
+        .map(|path| {
+            let stamp = stamp_of(&path, output_dir);
+            (path, stamp)
+        })
+        .collect()
+}
+
+// `output_dir` は絶対パス
+fn stamp_of(path: &Path, output_dir: &Path) -> Option<Stamp> {
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_dir() {
+        return Some(Stamp::File(metadata.modified().ok()));
+    }
+    let dir = std::path::absolute(path).ok()?;
+    let names = fs::read_dir(path)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name())
+        .filter(|name| dir.join(name) != output_dir)
+        .collect();
+    Some(Stamp::Dir(names))
+}
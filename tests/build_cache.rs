@@ -0,0 +1,95 @@
+//! 入力の変わっていないページを出力し直さないこと
+
+mod common;
+
+use bokuteki::BuildCache;
+use common::project_dir;
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn unchanged_pages_are_not_rewritten() {
+    let dir = project_dir("build-cache");
+    fs::write(dir.join("main.bok"), "import 'sub';\n\n# Main\n").unwrap();
+    fs::write(dir.join("sub.bok"), "# Sub\n").unwrap();
+    let mut config = common::config(&dir);
+    let main_html = dir.join("output/main.html");
+    let sub_html = dir.join("output/sub.html");
+    bokuteki::build(&config).unwrap();
+
+    // 出力を書き換えておき、出力し直されたかを調べる
+    fs::write(&main_html, "stale").unwrap();
+    fs::write(&sub_html, "stale").unwrap();
+    fs::write(dir.join("sub.bok"), "# Sub\n\nchanged\n").unwrap();
+    let report = bokuteki::build(&config).unwrap();
+    assert_eq!(report.files.len(), 2);
+    assert_eq!(fs::read_to_string(&main_html).unwrap(), "stale");
+    assert!(fs::read_to_string(&sub_html).unwrap().contains("changed"));
+
+    // 設定が変わればすべて出力し直す
+    config.page_options.theme = "dark".to_string();
+    bokuteki::build(&config).unwrap();
+    assert!(fs::read_to_string(&main_html)
+        .unwrap()
+        .contains(r#"data-theme="dark""#));
+
+    // 消されたページは出力し直す
+    fs::remove_file(&sub_html).unwrap();
+    bokuteki::build(&config).unwrap();
+    assert!(sub_html.is_file());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn pages_with_broken_includes_are_rebuilt() {
+    let dir = project_dir("build-cache-include");
+    fs::write(dir.join("main.bok"), "# Main\n\ninclude 'part#Sets';\n").unwrap();
+    fs::write(dir.join("part.bok"), "# Part\n").unwrap();
+    let config = common::config(&dir);
+    let mut cache = BuildCache::default();
+    let report = bokuteki::build_with_cache(&config, &mut cache).unwrap();
+    assert_eq!(report.files[0].errors[0].code, "unknown-include-selector");
+    assert!(!dir.join("output/main.html").exists());
+
+    // 取り込む見出しを足せば、同じキャッシュでのビルドでも取り込める
+    fs::write(dir.join("part.bok"), "# Part\n\n## Sets\n\nset section\n").unwrap();
+    let report = bokuteki::build_with_cache(&config, &mut cache).unwrap();
+    assert!(!report.has_errors(), "{:?}", report.files);
+    let paths: Vec<_> = report.files.iter().map(|file| file.path.clone()).collect();
+    assert_eq!(
+        paths,
+        vec![PathBuf::from("main.bok"), PathBuf::from("part.bok")]
+    );
+    assert!(fs::read_to_string(dir.join("output/main.html"))
+        .unwrap()
+        .contains("set section"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn pages_kept_because_of_errors_are_rebuilt_once_the_errors_clear() {
+    let dir = project_dir("build-cache-kept");
+    fs::write(dir.join("main.bok"), "first version\n").unwrap();
+    let mut config = common::config(&dir);
+    config.deny_warnings = true;
+    let main_html = dir.join("output/main.html");
+    bokuteki::build(&config).unwrap();
+
+    // リンク切れがエラーになり、前回のページが残る
+    fs::write(dir.join("main.bok"), "second [x](pic.png)\n").unwrap();
+    let report = bokuteki::build(&config).unwrap();
+    assert!(report.has_errors());
+    assert!(fs::read_to_string(&main_html)
+        .unwrap()
+        .contains("first version"));
+
+    // ソースを変えずにエラーの原因を取り除けば出力し直す
+    fs::write(dir.join("pic.png"), "").unwrap();
+    let report = bokuteki::build(&config).unwrap();
+    assert!(!report.has_errors(), "{:?}", report.files);
+    assert!(fs::read_to_string(&main_html).unwrap().contains("second"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,116 @@
+//! コマンドラインからのビルドの振る舞い
+
+mod common;
+
+use common::{bokuteki, project_dir};
+use std::fs;
+
+#[test]
+fn keep_going_writes_the_error_callout_and_fails() {
+    let dir = project_dir("cli-keep-going");
+    fs::write(dir.join("main.bok"), "# Main\n\n```rust\nfn main() {}\n").unwrap();
+
+    let output = bokuteki(&dir, &["build", "main.bok", "--math-mode", "client"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!dir.join("output/main.html").exists());
+
+    let output = bokuteki(
+        &dir,
+        &["build", "main.bok", "--keep-going", "--math-mode", "client"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unterminated code block"));
+    let html = fs::read_to_string(dir.join("output/main.html")).unwrap();
+    assert!(html.contains(r#"class="parse-error""#));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn exit_codes_follow_the_worst_diagnostic() {
+    let dir = project_dir("cli-exit-codes");
+    fs::write(dir.join("clean.bok"), "# Clean\n").unwrap();
+    fs::write(dir.join("warning.bok"), "# Warning\n\n[x](nowhere.html)\n").unwrap();
+    fs::write(dir.join("error.bok"), "# Error\n\n```\n").unwrap();
+    fs::write(dir.join("import.bok"), "import 'nowhere';\n\n# Import\n").unwrap();
+
+    let build = |entry: &str, deny_warnings: bool| {
+        let mut args = vec!["build", entry, "--math-mode", "client"];
+        if deny_warnings {
+            args.push("--deny-warnings");
+        }
+        bokuteki(&dir, &args).status.code()
+    };
+    assert_eq!(build("clean.bok", false), Some(0));
+    assert_eq!(build("clean.bok", true), Some(0));
+    // 警告だけなら成功し、--deny-warnings では誤りとして扱う
+    assert_eq!(build("warning.bok", false), Some(0));
+    assert_eq!(build("warning.bok", true), Some(1));
+    assert_eq!(build("error.bok", false), Some(1));
+    assert_eq!(build("import.bok", false), Some(2));
+    // 起点のファイルが見つからないのは、import 先が見つからないのとは区別する
+    assert_eq!(build("missing.bok", false), Some(3));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn config_is_discovered_from_a_subdirectory_and_overridden_by_flags() {
+    let dir = project_dir("cli-config");
+    fs::write(
+        dir.join("bokuteki.toml"),
+        "entry = \"index.bok\"\noutput = \"public\"\nmath = \"client\"\ntheme = \"dark\"\nlanguage = \"en\"\n",
+    )
+    .unwrap();
+    fs::write(dir.join("index.bok"), "# Index\n").unwrap();
+    let sub = dir.join("chapters");
+    fs::create_dir(&sub).unwrap();
+
+    // 引数なしでも親ディレクトリの bokuteki.toml を見つけ、出力先はその位置から解決する
+    let output = bokuteki(&sub, &["build"]);
+    assert_eq!(output.status.code(), Some(0));
+    let html = fs::read_to_string(dir.join("public/index.html")).unwrap();
+    assert!(html.contains(r#"data-theme="dark""#));
+    assert!(html.contains(r#"lang="en""#));
+
+    // コマンドラインの指定が設定ファイルより優先され、-o は作業ディレクトリから解決する
+    let output = bokuteki(
+        &sub,
+        &[
+            "build", "--theme", "light", "--lang", "fr", "-o", "../other",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let html = fs::read_to_string(dir.join("other/index.html")).unwrap();
+    assert!(html.contains(r#"data-theme="light""#));
+    assert!(html.contains(r#"lang="fr""#));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn init_refuses_to_overwrite_an_existing_project() {
+    let dir = project_dir("cli-init");
+
+    let output = bokuteki(&dir, &["init", "notes"]);
+    assert_eq!(output.status.code(), Some(0));
+    let notes = dir.join("notes");
+    assert!(notes.join("bokuteki.toml").exists());
+    let output = bokuteki(&notes, &["build", "--math-mode", "client"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    // 雛形と同じ名前のファイルが 1 つでもあれば何も書かない
+    let existing = dir.join("existing");
+    fs::create_dir(&existing).unwrap();
+    fs::write(existing.join("index.bok"), "# Mine\n").unwrap();
+    let output = bokuteki(&dir, &["init", "existing"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("refusing to overwrite"));
+    assert_eq!(
+        fs::read_to_string(existing.join("index.bok")).unwrap(),
+        "# Mine\n"
+    );
+    assert!(!existing.join("bokuteki.toml").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
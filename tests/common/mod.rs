@@ -0,0 +1,44 @@
+//! 結合テストで共有するプロジェクトの用意
+
+// テストごとに別のクレートとしてコンパイルされるので、使わない関数もある
+#![allow(dead_code)]
+
+use bokuteki::{BuildConfig, MathMode, PageOptions, RenderOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// 一時ディレクトリに空のプロジェクトのディレクトリを作る
+pub fn project_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bokuteki-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// `dir` の main.bok を起点として `dir/output` に出力する設定。数式はブラウザ側で描画する
+pub fn config(dir: &Path) -> BuildConfig {
+    BuildConfig {
+        entries: vec![dir.join("main.bok")],
+        root: dir.to_path_buf(),
+        output_dir: dir.join("output"),
+        template_dir: None,
+        search_paths: vec![],
+        render_options: RenderOptions {
+            math_mode: MathMode::Client,
+        },
+        page_options: PageOptions::default(),
+        keep_going: false,
+        deny_warnings: false,
+        force: false,
+    }
+}
+
+/// `dir` で `bokuteki` を `args` で実行する
+pub fn bokuteki(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bokuteki"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
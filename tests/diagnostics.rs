@@ -0,0 +1,145 @@
+//! 壊れた構文に対する診断と回復
+
+mod common;
+
+use bokuteki::diagnostic::Severity;
+use bokuteki::document::{BlockElement, InlineRange};
+use common::{config, project_dir};
+use std::fs;
+
+#[test]
+fn unterminated_code_fence_points_at_opening_line() {
+    let parsed = bokuteki::parse("intro\n\n```\nlet x = 1;\n\n# Heading\n\nThm. Foo\n  body");
+    assert_eq!(parsed.errors.len(), 1);
+    let error = &parsed.errors[0];
+    assert_eq!(error.message, "unterminated code block");
+    assert_eq!(
+        error.range,
+        InlineRange {
+            line: 2,
+            start_column: 0,
+            end_column: 3
+        }
+    );
+    assert_eq!(error.labels.len(), 1);
+    assert_eq!(
+        error.labels[0].range,
+        InlineRange {
+            line: 8,
+            start_column: 6,
+            end_column: 6
+        }
+    );
+    assert_eq!(
+        error.labels[0].message,
+        "reached end of file without closing \"```\""
+    );
+
+    // 開始行のあとの構文はそのままパースされる
+    let blocks = &parsed.document.block_elements;
+    assert!(matches!(blocks[1], BlockElement::ParseError { .. }));
+    assert!(matches!(blocks[3], BlockElement::Heading { level: 1, .. }));
+    assert!(matches!(blocks[4], BlockElement::Theorem { .. }));
+}
+
+#[test]
+fn unterminated_math_fence_in_nested_block() {
+    let parsed = bokuteki::parse("Thm. Foo\n  $$\n  x = y\n\n$$\nz\n$$\n");
+    assert_eq!(parsed.errors.len(), 1);
+    assert_eq!(parsed.errors[0].message, "unterminated math block");
+    assert_eq!(parsed.errors[0].range.line, 1);
+    assert_eq!(
+        parsed.errors[0].labels[0].message,
+        "reached end of enclosing block without closing \"$$\""
+    );
+    assert!(matches!(
+        parsed.document.block_elements[1],
+        BlockElement::Math { .. }
+    ));
+}
+
+#[test]
+fn unclosed_inline_delimiters_are_reported() {
+    let parsed = bokuteki::parse("price: $5 and `oops");
+    let messages: Vec<_> = parsed
+        .warnings
+        .iter()
+        .map(|warning| warning.message.as_str())
+        .collect();
+    assert_eq!(
+        messages,
+        ["unclosed inline math ('$').", "unclosed inline code ('`')."]
+    );
+    assert!(parsed.errors.is_empty());
+}
+
+#[test]
+fn render_like_rustc() {
+    let parsed = bokuteki::parse("# Title\n\nprice $5\n");
+    let rendered = parsed.warnings[0].render(
+        &parsed.source,
+        std::path::Path::new("notes/main.bok"),
+        Severity::Warning,
+        false,
+    );
+    assert_eq!(
+        rendered,
+        "warning[unclosed-inline]: unclosed inline math ('$').
+ --> notes/main.bok:3:9
+  |
+3 | price $5
+  |       - '$' opened here
+  |         ^
+  |
+  = note: inline elements must be closed on the same line
+"
+    );
+}
+
+#[test]
+fn lint_reports_unbalanced_math() {
+    let parsed = bokuteki::parse(
+        "$\\frac{a}{b$ and $\\left( x \\right]$\n\n$$\n\\begin{align}\nx\n\\end{aligned}\n$$\n",
+    );
+    let warnings =
+        bokuteki::lint::lint_document(&parsed.source, &parsed.document, std::path::Path::new("."));
+    let messages: Vec<_> = warnings
+        .iter()
+        .map(|warning| warning.message.as_str())
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "unclosed '{' in math.",
+            "'\\end' does not match '\\begin{align}' in math."
+        ]
+    );
+}
+
+#[test]
+fn math_mistakes_are_warnings_and_do_not_block_the_page() {
+    let dir = project_dir("math-lint");
+    fs::write(dir.join("main.bok"), "# Main\n\n$\\frac{1}{2$ is a half.\n").unwrap();
+
+    let report = bokuteki::build(&config(&dir)).unwrap();
+    assert!(!report.has_errors(), "{:?}", report.files);
+    let warnings: Vec<_> = report.files[0]
+        .warnings
+        .iter()
+        .map(|warning| warning.code)
+        .collect();
+    assert_eq!(warnings, vec!["invalid-math"]);
+    // 数式はブラウザ側の描画に任せて、ページは出力する
+    let html = fs::read_to_string(dir.join("output/main.html")).unwrap();
+    assert!(html.contains(r"\(\frac{1}{2\)"), "{}", html);
+
+    // 警告をエラーにすればページは出力しない
+    let mut denied = config(&dir);
+    denied.deny_warnings = true;
+    fs::remove_dir_all(dir.join("output")).unwrap();
+    let report = bokuteki::build(&denied).unwrap();
+    assert_eq!(report.files[0].errors[0].code, "invalid-math");
+    assert!(!dir.join("output/main.html").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,249 @@
+//! イベント列として読むパーサが、木を作るパーサと同じ内容を返すこと
+
+use bokuteki::document::{BlockElement, Derivation, InlineElement};
+use bokuteki::{Document, Event, Parser, Source, Tag};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+// 木をイベント列に平らにする。preamble と import は含めない
+fn flatten_blocks(events: &mut Vec<Event>, block_elements: &[BlockElement]) {
+    for block_element in block_elements {
+        match block_element {
+            BlockElement::Heading { level, content } => {
+                wrap(events, Tag::Heading { level: *level }, |events| {
+                    flatten_inlines(events, content)
+                });
+            }
+            BlockElement::Paragraph { content } => {
+                wrap(events, Tag::Paragraph, |events| {
+                    flatten_inlines(events, content)
+                });
+            }
+            BlockElement::Code {
+                excerpt: Some(excerpt),
+                ..
+            } => events.push(Event::Excerpt {
+                path: excerpt.path.clone(),
+                selector: excerpt.selector.clone(),
+            }),
+            BlockElement::Code { lines, .. } => wrap(events, Tag::CodeBlock, |events| {
+                events.extend(lines.iter().copied().map(Event::Text))
+            }),
+            BlockElement::Math { lines } => wrap(events, Tag::MathBlock, |events| {
+                events.extend(lines.iter().copied().map(Event::Text))
+            }),
+            BlockElement::Theorem {
+                kind,
+                title,
+                content,
+            } => wrap(events, Tag::Theorem { kind: *kind }, |events| {
+                wrap(events, Tag::TheoremTitle, |events| {
+                    flatten_inlines(events, title)
+                });
+                flatten_blocks(events, content);
+            }),
+            BlockElement::Proof { content } => {
+                wrap(events, Tag::Proof, |events| flatten_blocks(events, content))
+            }
+            BlockElement::Derivation(derivation) => wrap(events, Tag::Derivation, |events| {
+                flatten_derivation(events, derivation)
+            }),
+            BlockElement::List { mark_kind, items } => {
+                let tag = Tag::List {
+                    mark_kind: *mark_kind,
+                };
+                wrap(events, tag, |events| {
+                    for item in items {
+                        wrap(events, Tag::ListItem, |events| {
+                            flatten_inlines(events, &item.top_line);
+                            flatten_blocks(events, &item.blocks);
+                        });
+                    }
+                });
+            }
+            BlockElement::Blockquote { inner } => wrap(events, Tag::Blockquote, |events| {
+                flatten_blocks(events, inner)
+            }),
+            BlockElement::Include(include) => events.push(Event::Include {
+                path: include.path.clone(),
+                selector: include.selector.clone(),
+            }),
+            BlockElement::ParseError { lines, message } => events.push(Event::BlockParseError {
+                lines: lines.clone(),
+                message: message.clone(),
+            }),
+        }
+    }
+}
+
+fn flatten_derivation(events: &mut Vec<Event>, derivation: &Derivation) {
+    match derivation {
+        Derivation::InferenceRule {
+            premises,
+            conclusion,
+            rule_name,
+        } => wrap(events, Tag::InferenceRule, |events| {
+            for premise in premises {
+                flatten_derivation(events, premise);
+            }
+            wrap(events, Tag::Conclusion, |events| {
+                flatten_inlines(events, conclusion)
+            });
+            wrap(events, Tag::RuleName, |events| {
+                flatten_inlines(events, rule_name)
+            });
+        }),
+        Derivation::Leaf(content) => {
+            wrap(events, Tag::Leaf, |events| flatten_inlines(events, content))
+        }
+    }
+}
+
+fn flatten_inlines(events: &mut Vec<Event>, inline_elements: &[InlineElement]) {
+    for inline_element in inline_elements {
+        match inline_element {
+            InlineElement::Text { range } => events.push(Event::Text(*range)),
+            InlineElement::Link { text, url_range } => {
+                let tag = Tag::Link {
+                    url_range: *url_range,
+                };
+                wrap(events, tag, |events| flatten_inlines(events, text));
+            }
+            InlineElement::Code { range } => events.push(Event::Code(*range)),
+            InlineElement::Math { range } => events.push(Event::Math(*range)),
+            InlineElement::SmallCaps { range } => events.push(Event::SmallCaps(*range)),
+            InlineElement::ParseError { range, message } => events.push(Event::InlineParseError {
+                range: *range,
+                message: message.clone(),
+            }),
+        }
+    }
+}
+
+fn wrap(events: &mut Vec<Event>, tag: Tag, f: impl FnOnce(&mut Vec<Event>)) {
+    events.push(Event::Start(tag.clone()));
+    f(events);
+    events.push(Event::End(tag));
+}
+
+// `Start` と `End` が対応して入れ子になり、テキストなどは必ず何かの要素の中にあること
+fn assert_balanced(events: &[Event]) {
+    let mut stack = vec![];
+    for event in events {
+        match event {
+            Event::Start(tag) => stack.push(tag),
+            Event::End(tag) => assert_eq!(stack.pop(), Some(tag), "unbalanced: {:?}", events),
+            Event::Text(_)
+            | Event::Code(_)
+            | Event::Math(_)
+            | Event::SmallCaps(_)
+            | Event::InlineParseError { .. } => {
+                assert!(!stack.is_empty(), "{:?} outside of any element", event)
+            }
+            Event::Preamble { .. }
+            | Event::Import(_)
+            | Event::Include { .. }
+            | Event::Excerpt { .. }
+            | Event::BlockParseError { .. } => {}
+        }
+    }
+    assert!(stack.is_empty(), "unclosed: {:?}", stack);
+}
+
+// イベント列が木と同じ内容で、診断も同じであること
+fn check(text: &str) -> Vec<Event> {
+    let parsed = bokuteki::parse(text);
+    let src = Source::new(text);
+    let mut parser = Parser::new(&src);
+    let events: Vec<_> = parser.by_ref().collect();
+    assert_balanced(&events);
+
+    let Document {
+        preamble,
+        block_elements,
+        imports,
+    } = &parsed.document;
+    let mut expected = vec![];
+    flatten_blocks(&mut expected, block_elements);
+    let mut event_preamble = HashMap::new();
+    let mut event_imports = vec![];
+    let mut blocks = vec![];
+    for event in &events {
+        match event {
+            Event::Preamble { key, value } => {
+                event_preamble.insert(key.clone(), value.clone());
+            }
+            Event::Import(path) => event_imports.push(path.clone()),
+            _ => blocks.push(event.clone()),
+        }
+    }
+    assert_eq!(&event_preamble, preamble);
+    let imports: Vec<PathBuf> = imports.iter().map(|import| import.path.clone()).collect();
+    assert_eq!(event_imports, imports);
+    assert_eq!(blocks, expected);
+    assert_eq!(parser.errors(), &parsed.errors[..]);
+    assert_eq!(parser.warnings(), &parsed.warnings[..]);
+    events
+}
+
+#[test]
+fn events_match_the_tree_on_example_codes() {
+    let mut paths: Vec<_> = fs::read_dir("example-codes")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "bok"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        check(&fs::read_to_string(&path).unwrap());
+    }
+}
+
+#[test]
+fn nested_elements_and_errors_keep_their_ranges() {
+    let text = "%title Nested\nimport 'other';\n\nThm. [title](a.html)\n  - item [x](b.html)\n    > quote\n\n  a\n  b\n--- r\n  c\n\ninclude 'part#Sets';\n\nexcerpt 'lib.rs';\n\n`unclosed\n\n```\nunterminated\n";
+    let events = check(text);
+    for tag in [
+        Tag::TheoremTitle,
+        Tag::ListItem,
+        Tag::Blockquote,
+        Tag::InferenceRule,
+        Tag::Conclusion,
+        Tag::RuleName,
+        Tag::Leaf,
+    ] {
+        assert!(events.contains(&Event::Start(tag.clone())), "{:?}", tag);
+    }
+    let links = events
+        .iter()
+        .filter(|event| matches!(event, Event::Start(Tag::Link { .. })))
+        .count();
+    assert_eq!(links, 2);
+    assert!(events.contains(&Event::Include {
+        path: PathBuf::from("part"),
+        selector: Some("Sets".to_string()),
+    }));
+
+    // 失敗した箇所の範囲から、ソースの位置が分かる
+    let src = Source::new(text);
+    let inline_error = events.iter().find_map(|event| match event {
+        Event::InlineParseError { range, .. } => Some(*range),
+        _ => None,
+    });
+    let inline_error = inline_error.unwrap();
+    assert_eq!((inline_error.line, src.slice(&inline_error)), (16, "`"));
+    let block_error = events.iter().find_map(|event| match event {
+        Event::BlockParseError { lines, message } => Some((lines.clone(), message.as_str())),
+        _ => None,
+    });
+    // 閉じられていないブロックは開始行だけが失敗した範囲になる
+    let (lines, message) = block_error.unwrap();
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|line| (line.line, src.slice(line)))
+        .collect();
+    assert_eq!(lines, vec![(18, "```")]);
+    assert_eq!(message, "unterminated code block");
+}
@@ -0,0 +1,124 @@
+//! excerpt によるソースファイルからのコードの抜き出し
+
+mod common;
+
+use common::{config, project_dir};
+use std::fs;
+use std::path::PathBuf;
+
+const SOURCE: &str = "\
+use std::fmt;
+
+pub fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+mod tests {
+    // ANCHOR: check
+    fn check() {
+        assert!(1 < 2 && gcd(4, 6) == 2);
+    }
+    // ANCHOR_END: check
+}
+";
+
+#[test]
+fn excerpts_select_lines_markers_and_functions() {
+    let dir = project_dir("excerpt");
+    fs::create_dir_all(dir.join("src")).unwrap();
+    fs::write(dir.join("src/gcd.rs"), SOURCE).unwrap();
+    fs::write(
+        dir.join("main.bok"),
+        "# Main\n\nexcerpt 'src/gcd.rs#1';\n\nexcerpt 'src/gcd.rs#check';\n\nexcerpt 'src/gcd.rs#fn gcd';\n",
+    )
+    .unwrap();
+
+    let report = bokuteki::build(&config(&dir)).unwrap();
+    assert!(!report.has_errors(), "{:?}", report.files);
+    assert!(report.files[0].warnings.is_empty());
+    assert_eq!(report.excerpt_files, vec![PathBuf::from("src/gcd.rs")]);
+
+    let html = fs::read_to_string(dir.join("output/main.html")).unwrap();
+    assert!(html.contains("data-language=\"rust\""));
+    assert!(html.contains("use std::fmt;"));
+    // 目印の行は除き、字下げをそろえる
+    assert!(html.contains("fn check() {\n    assert!(1 &lt; 2 &amp;&amp; gcd(4, 6) == 2);\n}"));
+    assert!(!html.contains("ANCHOR"));
+    assert!(html.contains("pub fn gcd(a: u64, b: u64) -&gt; u64 {"));
+
+    // 抜き出したファイルが変われば出力し直す
+    fs::write(
+        dir.join("src/gcd.rs"),
+        SOURCE.replace("use std::fmt;", "use std::io;"),
+    )
+    .unwrap();
+    bokuteki::build(&config(&dir)).unwrap();
+    let html = fs::read_to_string(dir.join("output/main.html")).unwrap();
+    assert!(html.contains("use std::io;"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn vanished_markers_are_warned() {
+    let dir = project_dir("excerpt-warnings");
+    fs::write(dir.join("lib.py"), "def f(x):\n    return x\n").unwrap();
+    fs::write(
+        dir.join("main.bok"),
+        "excerpt 'lib.py#gone';\n\nexcerpt 'lib.py#fn f';\n\nexcerpt 'nothing.py';\n",
+    )
+    .unwrap();
+
+    let report = bokuteki::check(&[dir.join("main.bok")], &dir, &[], false).unwrap();
+    let file = &report.files[0];
+    let warnings: Vec<_> = file
+        .warnings
+        .iter()
+        .map(|warning| (warning.code, warning.range.line))
+        .collect();
+    assert_eq!(warnings, vec![("excerpt-not-found", 0)]);
+    let errors: Vec<_> = file
+        .errors
+        .iter()
+        .map(|error| (error.code, error.range.line))
+        .collect();
+    assert_eq!(errors, vec![("missing-excerpt", 4)]);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn excerpts_stay_inside_the_root_and_use_search_paths() {
+    let dir = project_dir("excerpt-root");
+    let root = dir.join("project");
+    fs::create_dir_all(root.join("lib")).unwrap();
+    fs::write(root.join("lib/util.rs"), "fn util() {}\n").unwrap();
+    fs::write(dir.join("secret.rs"), "secret\n").unwrap();
+    fs::write(
+        root.join("main.bok"),
+        "excerpt 'util.rs';\n\nexcerpt '../secret.rs';\n",
+    )
+    .unwrap();
+
+    let report = bokuteki::check(
+        &[root.join("main.bok")],
+        &root,
+        &[PathBuf::from("lib")],
+        false,
+    )
+    .unwrap();
+    assert_eq!(report.excerpt_files, vec![PathBuf::from("lib/util.rs")]);
+    let errors = &report.files[0].errors;
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, "missing-excerpt");
+    assert_eq!(errors[0].range.line, 2);
+    assert_eq!(
+        errors[0].notes,
+        vec![
+            "skipped `../secret.rs` because it is outside the project root",
+            "tried `secret.rs`",
+        ]
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+}
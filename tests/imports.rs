@@ -0,0 +1,71 @@
+//! import 先の探し方
+
+mod common;
+
+use common::project_dir;
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn imports_resolve_parents_unicode_names_and_search_paths() {
+    let dir = project_dir("imports");
+    for sub in ["lectures", "common", "lib"] {
+        fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    fs::write(
+        dir.join("lectures/第一回.bok"),
+        "import '../common/defs.v2';\nimport 'macros';\nimport 'nowhere';\nimport '../../outside';\n\n# 第一回\n",
+    )
+    .unwrap();
+    fs::write(dir.join("common/defs.v2.bok"), "# Defs\n").unwrap();
+    fs::write(dir.join("lib/macros.bok"), "# Macros\n").unwrap();
+    // import したファイルのディレクトリが検索パスより優先される
+    fs::write(dir.join("lectures/macros.bok"), "# Local macros\n").unwrap();
+
+    let report = bokuteki::check(
+        &[dir.join("lectures/第一回.bok")],
+        &dir,
+        &[PathBuf::from("lib")],
+        false,
+    )
+    .unwrap();
+    let paths: Vec<_> = report.files.iter().map(|file| file.path.clone()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("lectures/第一回.bok"),
+            PathBuf::from("common/defs.v2.bok"),
+            PathBuf::from("lectures/macros.bok"),
+        ]
+    );
+    assert!(report.missing_files.is_empty());
+
+    let errors = &report.files[0].errors;
+    assert_eq!(errors.len(), 2);
+    assert!(errors.iter().all(|error| error.code == "missing-import"));
+    assert_eq!(errors[0].range.line, 2);
+    assert_eq!(
+        errors[0].notes,
+        vec!["tried `lectures/nowhere.bok`", "tried `lib/nowhere.bok`"]
+    );
+    assert_eq!(
+        errors[1].notes,
+        vec![
+            "skipped `lectures/../../outside.bok` because it is outside the project root",
+            "skipped `lib/../../outside.bok` because it is outside the project root",
+        ]
+    );
+
+    // 検索パスにしかないファイルは検索パスから読む
+    fs::remove_file(dir.join("lectures/macros.bok")).unwrap();
+    let report = bokuteki::check(
+        &[dir.join("lectures/第一回.bok")],
+        &dir,
+        &[PathBuf::from("lib")],
+        false,
+    )
+    .unwrap();
+    assert_eq!(report.files[2].path, PathBuf::from("lib/macros.bok"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,101 @@
+//! include によるブロック要素の取り込み
+
+mod common;
+
+use common::{config, project_dir};
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn included_blocks_are_inlined() {
+    let dir = project_dir("include");
+    fs::create_dir_all(dir.join("common")).unwrap();
+    fs::write(
+        dir.join("main.bok"),
+        "# Main\n\ninclude 'common/defs#自然数';\n\ninclude 'common/defs#Sets';\n\ninclude 'common/note';\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("common/defs.bok"),
+        "# Defs\n\ndefinition. 自然数\n  natural numbers\n\n## Sets\n\nset section\n\n### Subsets\n\nsubset section\n\n## Other\n\nother section\n",
+    )
+    .unwrap();
+    fs::write(dir.join("common/note.bok"), "include 'inner';\n").unwrap();
+    fs::write(dir.join("common/inner.bok"), "inner note\n").unwrap();
+
+    let report = bokuteki::build(&config(&dir)).unwrap();
+    assert!(!report.has_errors(), "{:?}", report.files);
+    let paths: Vec<_> = report.files.iter().map(|file| file.path.clone()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("main.bok"),
+            PathBuf::from("common/defs.bok"),
+            PathBuf::from("common/note.bok"),
+            PathBuf::from("common/inner.bok"),
+        ]
+    );
+    // include されただけのファイルはページにならない
+    assert!(!dir.join("output/common/defs.html").exists());
+
+    let html = fs::read_to_string(dir.join("output/main.html")).unwrap();
+    for expected in [
+        "natural numbers",
+        "set section",
+        "subset section",
+        "inner note",
+    ] {
+        assert!(html.contains(expected), "{}", expected);
+    }
+    assert!(!html.contains("other section"));
+    assert!(!html.contains("Defs"));
+
+    // include したファイルが変われば出力し直す
+    fs::write(dir.join("common/inner.bok"), "changed note\n").unwrap();
+    bokuteki::build(&config(&dir)).unwrap();
+    let html = fs::read_to_string(dir.join("output/main.html")).unwrap();
+    assert!(html.contains("changed note"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn include_errors_point_to_their_own_files() {
+    let dir = project_dir("include-errors");
+    fs::write(
+        dir.join("main.bok"),
+        "include 'a';\ninclude 'missing';\ninclude 'a#Nothing';\n",
+    )
+    .unwrap();
+    fs::write(dir.join("a.bok"), "# A\n\ninclude 'b';\n").unwrap();
+    fs::write(dir.join("b.bok"), "text\n\n$x\n\ninclude 'a';\n").unwrap();
+
+    let report = bokuteki::check(&[dir.join("main.bok")], &dir, &[], false).unwrap();
+    let errors: Vec<_> = report
+        .files
+        .iter()
+        .flat_map(|file| {
+            file.errors.iter().map(move |error| {
+                (
+                    file.path.display().to_string(),
+                    error.code,
+                    error.range.line,
+                )
+            })
+        })
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            ("main.bok".to_string(), "missing-include", 1),
+            ("main.bok".to_string(), "unknown-include-selector", 2),
+            ("b.bok".to_string(), "include-cycle", 4),
+        ]
+    );
+    assert_eq!(report.files[0].errors[0].notes, vec!["tried `missing.bok`"]);
+    let b = report.files.last().unwrap();
+    assert_eq!(b.warnings.len(), 1);
+    assert_eq!(b.warnings[0].range.line, 2);
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,73 @@
+//! `.bok` へのリンクの書き換えと検査
+
+mod common;
+
+use bokuteki::BuildCache;
+use common::{config, project_dir};
+use std::fs;
+
+#[test]
+fn bok_links_are_rewritten_and_checked() {
+    let dir = project_dir("links");
+    fs::create_dir_all(dir.join("lectures")).unwrap();
+    fs::create_dir_all(dir.join("common")).unwrap();
+    fs::write(
+        dir.join("main.bok"),
+        "import 'lectures/a';\n\n# Main Page\n\n[a](lectures/a.bok#Sets) [b](lectures/a.bok#自然数) [c](./lectures/a.bok#Nothing)\n\n[d](other.bok) [e](https://example.com/x.bok)\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("lectures/a.bok"),
+        "# A\n\n## Sets\n\n[top](../main.bok#Main-Page)\n\ninclude '../common/part';\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("common/part.bok"),
+        "definition. 自然数\n  [back](../main.bok)\n",
+    )
+    .unwrap();
+    fs::write(dir.join("other.bok"), "# Other\n").unwrap();
+
+    let mut cache = BuildCache::default();
+    for _ in 0..2 {
+        // 使い回した結果でもリンクは検査し直す
+        let report = bokuteki::build_with_cache(&config(&dir), &mut cache).unwrap();
+        let errors: Vec<_> = report
+            .files
+            .iter()
+            .flat_map(|file| {
+                file.errors
+                    .iter()
+                    .map(move |error| (file.path.display().to_string(), error.code))
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("main.bok".to_string(), "unknown-link-anchor"),
+                ("main.bok".to_string(), "unknown-link-target"),
+            ]
+        );
+        assert_eq!(
+            report.files[0].errors[1].message,
+            "link target 'other.bok' is not part of the build."
+        );
+    }
+
+    // リンクの誤りではページの出力を止めない
+    let main = fs::read_to_string(dir.join("output/main.html")).unwrap();
+    assert!(main.contains(r#"id="Main-Page""#));
+    assert!(main.contains(r#"<a href="./lectures/a.html#Sets">a</a>"#));
+    assert!(main.contains(r#"<a href="./lectures/a.html#Nothing">c</a>"#));
+    assert!(main.contains(r#"<a href="./other.html">d</a>"#));
+    assert!(main.contains(r#"<a href="https://example.com/x.bok">e</a>"#));
+
+    let a = fs::read_to_string(dir.join("output/lectures/a.html")).unwrap();
+    assert!(a.contains(r#"id="Sets""#));
+    assert!(a.contains(r#"id="自然数""#));
+    assert!(a.contains(r#"<a href="../main.html#Main-Page">top</a>"#));
+    // include したファイルのリンクは、取り込んだページからの相対パスにする
+    assert!(a.contains(r#"<a href="../main.html">back</a>"#));
+
+    let _ = fs::remove_dir_all(&dir);
+}
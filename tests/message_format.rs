@@ -0,0 +1,163 @@
+//! `--message-format json` と `--message-format sarif` の出力
+
+mod common;
+
+use common::project_dir;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// 数式の対応の誤り (別の行を指すラベル付き、非 ASCII の文字の後ろ) とリンク切れの警告を含む文書
+const MAIN: &str = "# Main\n\n$$\n\\begin{aligned}\n𝑥 \\end{cases}\n$$\n\n[x](nowhere.html)\n";
+
+// `dir` で `bokuteki check main.bok missing.bok` を実行し、標準出力中の `dir` を `<root>` にする
+fn check(dir: &Path, format: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_bokuteki"))
+        .args([
+            "check",
+            "main.bok",
+            "missing.bok",
+            "--message-format",
+            format,
+        ])
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .replace(&dir.display().to_string(), "<root>")
+}
+
+fn position(line: usize, column: usize, utf16_column: usize) -> Value {
+    json!({ "line": line, "column": column, "utf16_column": utf16_column })
+}
+
+#[test]
+fn json_lines() {
+    let dir = project_dir("message-format-json");
+    fs::write(dir.join("bokuteki.toml"), "").unwrap();
+    fs::write(dir.join("main.bok"), MAIN).unwrap();
+
+    let diagnostics: Vec<Value> = check(&dir, "json")
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        diagnostics,
+        vec![
+            json!({
+                "file": "<root>/main.bok",
+                "severity": "warning",
+                "code": "invalid-math",
+                "message": "'\\end' does not match '\\begin{aligned}' in math.",
+                // 列は文字単位と UTF-16 単位で数える
+                "range": { "start": position(5, 3, 4), "end": position(5, 14, 15) },
+                "labels": [{
+                    "message": "opened here",
+                    "range": { "start": position(4, 1, 1), "end": position(4, 16, 16) },
+                }],
+                "notes": [],
+            }),
+            json!({
+                "file": "<root>/main.bok",
+                "severity": "warning",
+                "code": "broken-link",
+                "message": "link target not found: nowhere.html",
+                "range": { "start": position(8, 5, 5), "end": position(8, 17, 17) },
+                "labels": [],
+                "notes": [],
+            }),
+            json!({
+                "file": "<root>/missing.bok",
+                "severity": "error",
+                "code": "missing-entry",
+                "message": "input file not found: missing.bok",
+                "range": null,
+                "labels": [],
+                "notes": [],
+            }),
+        ]
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn sarif_log() {
+    let dir = project_dir("message-format-sarif");
+    fs::write(dir.join("bokuteki.toml"), "").unwrap();
+    fs::write(dir.join("main.bok"), MAIN).unwrap();
+
+    let log: Value = serde_json::from_str(&check(&dir, "sarif")).unwrap();
+    let main = json!({ "uri": "main.bok", "uriBaseId": "PROJECTROOT" });
+    assert_eq!(
+        log,
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "bokuteki",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": [
+                            { "id": "broken-link" },
+                            { "id": "invalid-math" },
+                            { "id": "missing-entry" },
+                        ],
+                    },
+                },
+                "originalUriBaseIds": {
+                    "PROJECTROOT": { "uri": "file://<root>/" },
+                },
+                "results": [
+                    {
+                        "ruleId": "invalid-math",
+                        "level": "warning",
+                        "message": { "text": "'\\end' does not match '\\begin{aligned}' in math." },
+                        "locations": [{
+                            "physicalLocation": {
+                                "artifactLocation": main,
+                                // SARIF の列は UTF-16 単位
+                                "region": { "startLine": 5, "startColumn": 4, "endLine": 5, "endColumn": 15 },
+                            },
+                        }],
+                        "relatedLocations": [{
+                            "message": { "text": "opened here" },
+                            "physicalLocation": {
+                                "artifactLocation": main,
+                                "region": { "startLine": 4, "startColumn": 1, "endLine": 4, "endColumn": 16 },
+                            },
+                        }],
+                    },
+                    {
+                        "ruleId": "broken-link",
+                        "level": "warning",
+                        "message": { "text": "link target not found: nowhere.html" },
+                        "locations": [{
+                            "physicalLocation": {
+                                "artifactLocation": main,
+                                "region": { "startLine": 8, "startColumn": 5, "endLine": 8, "endColumn": 17 },
+                            },
+                        }],
+                        "relatedLocations": [],
+                    },
+                    {
+                        "ruleId": "missing-entry",
+                        "level": "error",
+                        "message": { "text": "input file not found: missing.bok" },
+                        "locations": [{
+                            "physicalLocation": {
+                                "artifactLocation": { "uri": "missing.bok", "uriBaseId": "PROJECTROOT" },
+                            },
+                        }],
+                    },
+                ],
+            }],
+        })
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,179 @@
+//! どんな入力でもパーサとプリンタが panic しないことを確かめる
+//!
+//! 構文の断片をランダムにつなげた文書を大量に作り、パース・イベント列・走査・HTML 出力を通す。
+//! 乱数は種を固定した xorshift なので、失敗した入力は毎回再現できる。
+
+use bokuteki::diagnostic::Severity;
+use bokuteki::document::{BlockElement, BlockRange, InlineElement, InlineRange};
+use bokuteki::visit::{self, NodePath, Visit};
+use bokuteki::{MathMode, Parser, RenderOptions, Source};
+use std::path::Path;
+
+const FRAGMENTS: &[&str] = &[
+    "",
+    " ",
+    "  ",
+    "\n",
+    "\r\n",
+    "\n\n",
+    "#",
+    "## ",
+    "```",
+    "$$",
+    "$",
+    "`",
+    "%",
+    "[",
+    "]",
+    "(",
+    ")",
+    "](",
+    "[a](b)",
+    "$x$",
+    "`c`",
+    "%sc%",
+    "---",
+    "--- name",
+    "- ",
+    "> ",
+    "Thm. ",
+    "Def. ",
+    "Proof.",
+    "proof.",
+    "import",
+    "import '",
+    "import 'a/b';",
+    "import '';",
+    "% title ",
+    "%key",
+    "text",
+    "あ",
+    "数式",
+    "é",
+    "😀",
+    "\t",
+    ";",
+    "'",
+    "\\",
+];
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0 = x;
+        x
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn generate(rng: &mut XorShift) -> String {
+    let len = rng.below(60);
+    (0..len)
+        .map(|_| FRAGMENTS[rng.below(FRAGMENTS.len())])
+        .collect()
+}
+
+// 木の中のすべての range がソースの文字境界を指していることを確かめる
+struct RangeChecker<'a, 'b> {
+    src: &'b Source<'a>,
+}
+
+impl RangeChecker<'_, '_> {
+    fn check(&self, range: &InlineRange) {
+        let line = self
+            .src
+            .line(range.line)
+            .unwrap_or_else(|| panic!("line out of bounds: {:?}", range));
+        assert!(
+            range.start_column <= range.end_column && range.end_column <= line.len(),
+            "range out of bounds: {:?}",
+            range
+        );
+        assert!(
+            line.is_char_boundary(range.start_column) && line.is_char_boundary(range.end_column),
+            "range not on char boundary: {:?}",
+            range
+        );
+    }
+
+    fn check_block(&self, range: &BlockRange) {
+        range.iter().for_each(|line| self.check(line));
+    }
+}
+
+impl Visit for RangeChecker<'_, '_> {
+    fn visit_block_element(&mut self, block_element: &BlockElement, path: &mut NodePath) {
+        match block_element {
+            BlockElement::Code { lines, .. }
+            | BlockElement::Math { lines }
+            | BlockElement::ParseError { lines, .. } => self.check_block(lines),
+            _ => {}
+        }
+        visit::walk_block_element(self, block_element, path);
+    }
+
+    fn visit_inline_element(&mut self, inline_element: &InlineElement, path: &mut NodePath) {
+        match inline_element {
+            InlineElement::Text { range }
+            | InlineElement::Code { range }
+            | InlineElement::Math { range }
+            | InlineElement::SmallCaps { range }
+            | InlineElement::ParseError { range, .. } => self.check(range),
+            InlineElement::Link { url_range, .. } => self.check(url_range),
+        }
+        visit::walk_inline_element(self, inline_element, path);
+    }
+}
+
+fn check(text: &str) {
+    let parsed = bokuteki::parse(text);
+    let mut checker = RangeChecker {
+        src: &parsed.source,
+    };
+    checker.visit_document(&parsed.document);
+    for diagnostic in parsed.errors.iter().chain(&parsed.warnings) {
+        checker.check(&diagnostic.range);
+        for label in &diagnostic.labels {
+            checker.check(&label.range);
+        }
+        diagnostic.render(&parsed.source, Path::new("fuzz.bok"), Severity::Error, true);
+    }
+
+    let options = RenderOptions {
+        math_mode: MathMode::Client,
+    };
+    bokuteki::render_html(&parsed.source, &parsed.document.block_elements, &options);
+
+    let mut parser = Parser::new(&parsed.source);
+    parser.by_ref().for_each(drop);
+    assert_eq!(parser.errors(), parsed.errors.as_slice());
+}
+
+#[test]
+fn random_documents_do_not_panic() {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    for _ in 0..5000 {
+        let text = generate(&mut rng);
+        let result = std::panic::catch_unwind(|| check(&text));
+        assert!(result.is_ok(), "panicked on input: {:?}", text);
+    }
+}
+
+#[test]
+fn truncated_documents_do_not_panic() {
+    // 正しい文書を途中で切ると、閉じていない構文が必ずどこかにできる
+    let text = "% title Sample\nimport 'a/b';\n\n# Heading $x$\n\nThm. [link](url)\n  \
+                body `code`\n\nproof.\n  done\n\n```\ncode\n```\n\n$$\nx\n$$\n\n  a\n  b\n\
+                --- rule\n  c\n\n- item\n  - 入れ子\n\n> quote\n";
+    for (end, _) in text.char_indices() {
+        check(&text[..end]);
+    }
+}
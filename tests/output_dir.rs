@@ -0,0 +1,111 @@
+//! 出力ディレクトリの扱い。bokuteki が出力したもの以外は消さないこと
+
+mod common;
+
+use common::{config, project_dir};
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn non_empty_directory_without_marker_is_refused() {
+    let dir = project_dir("output-refuse");
+    fs::write(dir.join("main.bok"), "# Main\n").unwrap();
+    fs::create_dir_all(dir.join("output")).unwrap();
+    fs::write(dir.join("output/notes.txt"), "mine").unwrap();
+
+    let err = bokuteki::build(&config(&dir)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    assert!(err.to_string().contains("--force"));
+    assert!(!dir.join("output/main.html").exists());
+    assert_eq!(
+        fs::read_to_string(dir.join("output/notes.txt")).unwrap(),
+        "mine"
+    );
+
+    // --force なら書き込むが、もとからあったファイルには触れない
+    let mut forced = config(&dir);
+    forced.force = true;
+    bokuteki::build(&forced).unwrap();
+    assert!(dir.join("output/main.html").is_file());
+    // 2 回目からは目印があるので --force はいらない
+    bokuteki::build(&config(&dir)).unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.join("output/notes.txt")).unwrap(),
+        "mine"
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn only_stale_owned_files_are_removed() {
+    let dir = project_dir("output-stale");
+    fs::create_dir_all(dir.join("part")).unwrap();
+    fs::write(dir.join("main.bok"), "import 'part/sub';\n\n# Main\n").unwrap();
+    fs::write(dir.join("part/sub.bok"), "# Sub\n").unwrap();
+    bokuteki::build(&config(&dir)).unwrap();
+    assert!(dir.join("output/part/sub.html").is_file());
+    fs::write(dir.join("output/extra.txt"), "mine").unwrap();
+
+    // import しなくなったページは消え、空になったディレクトリも片付ける
+    fs::write(dir.join("main.bok"), "# Main\n").unwrap();
+    bokuteki::build(&config(&dir)).unwrap();
+    assert!(!dir.join("output/part").exists());
+    assert!(dir.join("output/main.html").is_file());
+    assert!(dir.join("output/bokuteki.css").is_file());
+    assert_eq!(
+        fs::read_to_string(dir.join("output/extra.txt")).unwrap(),
+        "mine"
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn marker_entries_outside_the_output_are_ignored() {
+    let dir = project_dir("output-marker");
+    fs::write(dir.join("main.bok"), "# Main\n").unwrap();
+    fs::create_dir_all(dir.join("output")).unwrap();
+    fs::write(dir.join("keep.txt"), "outside").unwrap();
+    fs::write(dir.join("output/old.html"), "old").unwrap();
+    fs::write(
+        dir.join("output/.bokuteki-output"),
+        format!(
+            "# files written by bokuteki. do not edit.\n\n# comment\nold.html\n../keep.txt\n{}\n",
+            dir.join("keep.txt").display()
+        ),
+    )
+    .unwrap();
+
+    bokuteki::build(&config(&dir)).unwrap();
+    assert!(!dir.join("output/old.html").exists());
+    assert_eq!(fs::read_to_string(dir.join("keep.txt")).unwrap(), "outside");
+    let marker = fs::read_to_string(dir.join("output/.bokuteki-output")).unwrap();
+    assert_eq!(
+        marker,
+        "# files written by bokuteki. do not edit.\n.bokuteki-cache.json\nbokuteki.css\nbokuteki.js\nmain.html\n"
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn output_into_the_source_directory_exits_with_io_error() {
+    let dir = project_dir("output-source-dir");
+    fs::write(dir.join("main.bok"), "# Main\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bokuteki"))
+        .args(["build", "main.bok", "--output", "."])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("refusing to write"));
+    assert_eq!(
+        fs::read_to_string(dir.join("main.bok")).unwrap(),
+        "# Main\n"
+    );
+    assert!(!dir.join("main.html").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
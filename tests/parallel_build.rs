@@ -0,0 +1,123 @@
+//! 並行にビルドしても結果の順序が変わらないこと
+
+mod common;
+
+use bokuteki::BuildConfig;
+use common::project_dir;
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn diagnostics_are_reported_in_import_order() {
+    let dir = project_dir("parallel-build");
+    // main -> part00..part19 -> (part の半分は) part00 を再び import する
+    let mut main = String::new();
+    for i in 0..20 {
+        main += &format!("import 'part{:02}';\n", i);
+        let import = if i % 2 == 1 { "import 'part00';\n" } else { "" };
+        fs::write(
+            dir.join(format!("part{:02}.bok", i)),
+            format!("{}\n# Part {}\n\nprice ${}\n", import, i, i),
+        )
+        .unwrap();
+    }
+    main += "import 'missing';\n\n# Main\n";
+    fs::write(dir.join("main.bok"), main).unwrap();
+
+    let expected: Vec<_> = std::iter::once("main.bok".to_string())
+        .chain((0..20).map(|i| format!("part{:02}.bok", i)))
+        .collect();
+    let config = common::config(&dir);
+    for _ in 0..5 {
+        let _ = fs::remove_dir_all(dir.join("output"));
+        for report in [
+            bokuteki::build(&config).unwrap(),
+            bokuteki::check(&config.entries, &config.root, &[], false).unwrap(),
+        ] {
+            let paths: Vec<_> = report
+                .files
+                .iter()
+                .map(|file| file.path.display().to_string())
+                .collect();
+            assert_eq!(paths, expected);
+            assert!(report.missing_files.is_empty());
+            assert!(report.has_missing_files());
+            let missing: Vec<_> = report.files[0]
+                .errors
+                .iter()
+                .map(|error| (error.code, error.notes.clone()))
+                .collect();
+            assert_eq!(
+                missing,
+                vec![("missing-import", vec!["tried `missing.bok`".to_string()])]
+            );
+            for file in &report.files[1..] {
+                assert_eq!(file.warnings.len(), 1, "{}", file.path.display());
+            }
+        }
+        assert!(dir.join("output/part19.html").is_file());
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn several_entries_share_one_output_tree() {
+    let dir = project_dir("several-entries");
+    fs::create_dir_all(dir.join("lectures")).unwrap();
+    fs::create_dir_all(dir.join("appendix")).unwrap();
+    fs::write(dir.join("lectures/l1.bok"), "import 'shared';\n\n# L1\n").unwrap();
+    fs::write(dir.join("lectures/l2.bok"), "import 'shared';\n\n# L2\n").unwrap();
+    fs::write(dir.join("lectures/shared.bok"), "# Shared\n").unwrap();
+    fs::write(dir.join("appendix/a.bok"), "# A\n").unwrap();
+
+    let entries = bokuteki::expand_entries(&[
+        dir.join("lectures/l*.bok"),
+        dir.join("lectures/l1.bok"),
+        dir.join("appendix/a.bok"),
+    ])
+    .unwrap();
+    assert_eq!(
+        entries,
+        vec![
+            dir.join("lectures/l1.bok"),
+            dir.join("lectures/l2.bok"),
+            dir.join("appendix/a.bok"),
+        ]
+    );
+    let root = bokuteki::common_root(&entries);
+    assert_eq!(root, dir);
+    assert!(bokuteki::expand_entries(&[dir.join("nothing/*.bok")]).is_err());
+
+    let config = BuildConfig {
+        entries,
+        root,
+        ..common::config(&dir)
+    };
+    let report = bokuteki::build(&config).unwrap();
+    let paths: Vec<_> = report.files.iter().map(|file| file.path.clone()).collect();
+    // 共有されたファイルは 1 度だけ処理される
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("lectures/l1.bok"),
+            PathBuf::from("lectures/l2.bok"),
+            PathBuf::from("appendix/a.bok"),
+            PathBuf::from("lectures/shared.bok"),
+        ]
+    );
+    for page in [
+        "lectures/l1",
+        "lectures/l2",
+        "lectures/shared",
+        "appendix/a",
+    ] {
+        assert!(dir
+            .join("output")
+            .join(page)
+            .with_extension("html")
+            .is_file());
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
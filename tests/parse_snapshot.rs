@@ -0,0 +1,194 @@
+//! example-codes のパース結果が、ブロックのパーサを書き直す前と変わらないこと
+//!
+//! tests/snapshots/parse/*.txt は書き直す前のパーサの結果を、下の `dump` と同じ形式で書き出したもの。
+//! 範囲は (行, 中身) で表すので、列の数え方によらない。
+//! ただし閉じられていないインラインの区切り文字は、パースエラーから回復するようにした際に
+//! Text から ParseError に変えたので、codeblock.txt のその 2 箇所だけ書き換えてある。
+
+use bokuteki::document::{
+    BlockElement, BlockRange, Derivation, InlineElement, InlineRange, Source,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+struct Dumper<'a> {
+    src: &'a Source<'a>,
+    out: String,
+}
+
+impl Dumper<'_> {
+    fn line(&mut self, depth: usize, text: &str) {
+        self.out += &"  ".repeat(depth);
+        self.out += text;
+        self.out.push('\n');
+    }
+
+    fn range(&self, range: &InlineRange) -> String {
+        format!("{}:{:?}", range.line, self.src.slice(range))
+    }
+
+    fn lines(&mut self, lines: &BlockRange, depth: usize) {
+        for line in lines {
+            let text = format!("| {}", self.range(line));
+            self.line(depth, &text);
+        }
+    }
+
+    fn blocks(&mut self, block_elements: &[BlockElement], depth: usize) {
+        for block_element in block_elements {
+            self.block(block_element, depth);
+        }
+    }
+
+    fn block(&mut self, block_element: &BlockElement, depth: usize) {
+        match block_element {
+            BlockElement::Heading { level, content } => {
+                self.line(depth, &format!("Heading {}", level));
+                self.inlines(content, depth + 1);
+            }
+            BlockElement::Paragraph { content } => {
+                self.line(depth, "Paragraph");
+                self.inlines(content, depth + 1);
+            }
+            BlockElement::Code { lines, .. } => {
+                self.line(depth, "Code");
+                self.lines(lines, depth + 1);
+            }
+            BlockElement::Math { lines } => {
+                self.line(depth, "Math");
+                self.lines(lines, depth + 1);
+            }
+            BlockElement::Theorem {
+                kind,
+                title,
+                content,
+            } => {
+                self.line(depth, &format!("Theorem {:?}", kind));
+                self.line(depth + 1, "title:");
+                self.inlines(title, depth + 2);
+                self.line(depth + 1, "content:");
+                self.blocks(content, depth + 2);
+            }
+            BlockElement::Proof { content } => {
+                self.line(depth, "Proof");
+                self.blocks(content, depth + 1);
+            }
+            BlockElement::Derivation(derivation) => {
+                self.line(depth, "Derivation");
+                self.derivation(derivation, depth + 1);
+            }
+            BlockElement::List { items, .. } => {
+                self.line(depth, "List");
+                for item in items {
+                    self.line(depth + 1, "Item");
+                    self.line(depth + 2, "top_line:");
+                    self.inlines(&item.top_line, depth + 3);
+                    self.line(depth + 2, "blocks:");
+                    self.blocks(&item.blocks, depth + 3);
+                }
+            }
+            BlockElement::Blockquote { inner } => {
+                self.line(depth, "Blockquote");
+                self.blocks(inner, depth + 1);
+            }
+            BlockElement::Include(include) => {
+                let text = format!("Include {}", self.range(&include.range));
+                self.line(depth, &text);
+            }
+            BlockElement::ParseError { .. } => self.line(depth, "ParseError"),
+        }
+    }
+
+    fn derivation(&mut self, derivation: &Derivation, depth: usize) {
+        match derivation {
+            Derivation::InferenceRule {
+                premises,
+                conclusion,
+                rule_name,
+            } => {
+                self.line(depth, "Rule");
+                self.line(depth + 1, "premises:");
+                for premise in premises {
+                    self.derivation(premise, depth + 2);
+                }
+                self.line(depth + 1, "conclusion:");
+                self.inlines(conclusion, depth + 2);
+                self.line(depth + 1, "rule_name:");
+                self.inlines(rule_name, depth + 2);
+            }
+            Derivation::Leaf(content) => {
+                self.line(depth, "Leaf");
+                self.inlines(content, depth + 1);
+            }
+        }
+    }
+
+    fn inlines(&mut self, inline_elements: &[InlineElement], depth: usize) {
+        for inline_element in inline_elements {
+            let text = match inline_element {
+                InlineElement::Text { range } => format!("Text {}", self.range(range)),
+                InlineElement::Code { range } => format!("Code {}", self.range(range)),
+                InlineElement::Math { range } => format!("Math {}", self.range(range)),
+                InlineElement::SmallCaps { range } => format!("SmallCaps {}", self.range(range)),
+                InlineElement::Link { url_range, .. } => format!("Link {}", self.range(url_range)),
+                InlineElement::ParseError { .. } => "ParseError".to_string(),
+            };
+            self.line(depth, &text);
+            if let InlineElement::Link { text, .. } = inline_element {
+                self.inlines(text, depth + 1);
+            }
+        }
+    }
+}
+
+fn dump(text: &str) -> String {
+    let parsed = bokuteki::parse(text);
+    let mut dumper = Dumper {
+        src: &parsed.source,
+        out: String::new(),
+    };
+    dumper.blocks(&parsed.document.block_elements, 0);
+    dumper.out
+}
+
+const SNAPSHOT_DIR: &str = "tests/snapshots/parse";
+
+// `dir` の `.bok` のファイルを名前順に返す
+fn bok_files(dir: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "bok"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+    paths
+}
+
+fn check_snapshot(path: &Path) {
+    let snapshot = Path::new(SNAPSHOT_DIR)
+        .join(path.file_stem().unwrap())
+        .with_extension("txt");
+    let text = fs::read_to_string(path).unwrap();
+    assert_eq!(
+        dump(&text),
+        fs::read_to_string(&snapshot).unwrap(),
+        "{}",
+        path.display()
+    );
+}
+
+#[test]
+fn example_codes_parse_as_before() {
+    for path in bok_files("example-codes") {
+        check_snapshot(&path);
+    }
+}
+
+// 段落の終わりの判定。スナップショットの隣にある paragraph-*.bok をパースする
+#[test]
+fn paragraphs_end_where_they_did_before() {
+    for path in bok_files(SNAPSHOT_DIR) {
+        check_snapshot(&path);
+    }
+}
@@ -0,0 +1,88 @@
+//! 1 つの文書をファイルに出力せずに HTML にする
+
+use bokuteki::{MathMode, PageOptions, RenderOptions, Template};
+use std::path::Path;
+
+const OPTIONS: RenderOptions = RenderOptions {
+    math_mode: MathMode::Client,
+};
+
+#[test]
+fn renders_fragment_without_following_imports() {
+    let (html, report) = bokuteki::render(
+        "import 'missing';\n\n# Title\n\n$x$\n",
+        Path::new("notes/doc.bok"),
+        &OPTIONS,
+        None,
+    );
+    assert!(html.starts_with("<h1"), "{}", html);
+    assert!(html.contains(r"\(x\)"));
+    assert!(!html.contains("<html"));
+    assert_eq!(report.path, Path::new("notes/doc.bok"));
+    assert!(report.errors.is_empty());
+}
+
+#[test]
+fn renders_full_page_and_reports_errors() {
+    let template = Template::embedded();
+    let page_options = PageOptions {
+        base_url: Some("https://example.com/notes".to_string()),
+        ..PageOptions::default()
+    };
+    let (html, report) = bokuteki::render(
+        "%title Doc\n\n```\nunterminated\n",
+        Path::new("doc.bok"),
+        &OPTIONS,
+        Some((&template, &page_options)),
+    );
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<title>Doc</title>"));
+    assert!(html.contains(r#"<link rel="canonical" href="https://example.com/notes/doc.html">"#));
+    // 失敗した箇所もエラー表示として出力される
+    assert!(html.contains(r#"class="parse-error""#));
+    assert_eq!(report.errors.len(), 1);
+}
+
+#[test]
+fn closed_stdout_does_not_panic() {
+    let dir = std::env::temp_dir().join(format!("bokuteki-render-pipe-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    // パイプの容量より大きい出力にして、書いている途中で読み手が閉じるようにする
+    std::fs::write(dir.join("big.bok"), "paragraph\n\n".repeat(20000)).unwrap();
+
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_bokuteki"))
+        .args(["render", "--page", "big.bok"])
+        .current_dir(&dir)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr);
+    assert!(stderr.is_empty(), "{}", stderr);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn attributes_are_printed_in_a_fixed_order() {
+    let (html, _) = bokuteki::render(
+        "# Main Page\n\nThm. Sets\n  body\n",
+        Path::new("doc.bok"),
+        &OPTIONS,
+        None,
+    );
+    let tags: Vec<_> = html.lines().filter(|line| line.starts_with('<')).collect();
+    assert_eq!(
+        tags,
+        vec![
+            r#"<h1 class="block" id="Main-Page">"#,
+            "</h1>",
+            r#"<div class="math-theorem" id="Sets" data-title="Sets">"#,
+            "</div>",
+        ]
+    );
+}
@@ -0,0 +1,86 @@
+//! プレビューサーバにループバックで接続する
+
+mod common;
+
+use common::project_dir;
+use std::fs;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn get(port: u16, path: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    stream
+        .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+// `needle` を受け取るまで読む
+async fn read_until(stream: &mut TcpStream, needle: &str) -> String {
+    let mut received = String::new();
+    let mut buf = [0; 1024];
+    while !received.contains(needle) {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(
+            n > 0,
+            "connection closed before {:?}: {:?}",
+            needle,
+            received
+        );
+        received += &String::from_utf8_lossy(&buf[..n]);
+    }
+    received
+}
+
+#[tokio::test]
+async fn serves_pages_and_pushes_reloads() {
+    let dir = project_dir("serve");
+    fs::write(dir.join("main.bok"), "# Main\n\nhello\n").unwrap();
+    let config = common::config(&dir);
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (built_sender, mut built) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(bokuteki::serve::serve(config, listener, move |result| {
+        built_sender.send(result.is_ok()).unwrap();
+    }));
+    assert!(built.recv().await.unwrap());
+
+    let page = get(port, "/main.html").await;
+    assert!(page.starts_with("HTTP/1.1 200 OK\r\n"), "{}", page);
+    assert!(page.contains("hello"));
+    assert!(page.contains(r#"<script src="/__bokuteki/livereload.js"></script>"#));
+
+    assert!(get(port, "/__bokuteki/livereload.js")
+        .await
+        .contains("EventSource"));
+    assert!(get(port, "/missing.html")
+        .await
+        .starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert!(get(port, "/../main.bok")
+        .await
+        .starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let mut events = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    events
+        .write_all(b"GET /__bokuteki/events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let head = read_until(&mut events, ": connected\n\n").await;
+    assert!(head.contains("Content-Type: text/event-stream"));
+
+    // 更新時刻の分解能より後に書き換える
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    fs::write(dir.join("main.bok"), "# Main\n\nupdated\n").unwrap();
+    assert!(built.recv().await.unwrap());
+    let event = tokio::time::timeout(Duration::from_secs(10), read_until(&mut events, "\n\n"))
+        .await
+        .unwrap();
+    assert_eq!(event, "event: reload\ndata: {\"full\":false}\n\n");
+    assert!(get(port, "/main.html").await.contains("updated"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,100 @@
+//! 木の走査と作り直し
+
+use bokuteki::document::{BlockElement, InlineElement};
+use bokuteki::visit::{self, Field, Fold, NodePath, Visit};
+
+// 数式ごとに、根からの経路の (フィールド, 添字) を集める
+#[derive(Default)]
+struct MathPaths(Vec<Vec<(Field, usize)>>);
+
+impl Visit for MathPaths {
+    fn visit_inline_element(&mut self, inline_element: &InlineElement, path: &mut NodePath) {
+        if let InlineElement::Math { .. } = inline_element {
+            let segments = path.segments().iter();
+            self.0.push(
+                segments
+                    .map(|segment| (segment.field, segment.index))
+                    .collect(),
+            );
+        }
+        visit::walk_inline_element(self, inline_element, path);
+    }
+}
+
+#[test]
+fn each_child_field_has_its_own_index_space() {
+    let parsed = bokuteki::parse("Thm. $t$\n  $c$\n\n  $p$\n---$r$\n  $q$\n");
+    let mut paths = MathPaths::default();
+    paths.visit_document(&parsed.document);
+    use Field::*;
+    assert_eq!(
+        paths.0,
+        vec![
+            vec![(BlockElements, 0), (Title, 0)],
+            vec![(BlockElements, 0), (Content, 0), (Content, 0)],
+            vec![
+                (BlockElements, 1),
+                (Derivation, 0),
+                (Premises, 0),
+                (Leaf, 0)
+            ],
+            // 結論と規則の名前はどちらも先頭の要素だが、フィールドで区別できる
+            vec![(BlockElements, 1), (Derivation, 0), (Conclusion, 0)],
+            vec![(BlockElements, 1), (Derivation, 0), (RuleName, 0)],
+        ]
+    );
+}
+
+// 数式のブロックを消し、証明をその中身で置き換える
+#[derive(Default)]
+struct Flatten(Vec<(Field, usize)>);
+
+impl Fold for Flatten {
+    fn fold_block_element(
+        &mut self,
+        block_element: BlockElement,
+        path: &mut NodePath,
+    ) -> Vec<BlockElement> {
+        let segment = path.current().unwrap();
+        self.0.push((segment.field, segment.index));
+        match block_element {
+            BlockElement::Math { .. } => vec![],
+            BlockElement::Proof { content } => {
+                visit::walk_block_elements_fold(self, content, Field::Content, path)
+            }
+            _ => visit::walk_block_element_fold(self, block_element, path),
+        }
+    }
+}
+
+#[test]
+fn fold_removes_and_inserts_nodes() {
+    let parsed = bokuteki::parse("# A\n\n$$\nx\n$$\n\nproof.\n  p\n\nlast\n");
+    let mut flatten = Flatten::default();
+    let document = flatten.fold_document(parsed.document);
+    let lines: Vec<_> = document
+        .block_elements
+        .iter()
+        .map(|block_element| match block_element {
+            BlockElement::Heading { content, .. } | BlockElement::Paragraph { content } => {
+                match content[..] {
+                    [InlineElement::Text { range }] => range.line,
+                    _ => panic!("unexpected content: {:?}", content),
+                }
+            }
+            _ => panic!("unexpected block: {:?}", block_element),
+        })
+        .collect();
+    assert_eq!(lines, vec![0, 7, 9]);
+    // 経路の添字は作り直す前の位置を表す
+    assert_eq!(
+        flatten.0,
+        vec![
+            (Field::BlockElements, 0),
+            (Field::BlockElements, 1),
+            (Field::BlockElements, 2),
+            (Field::Content, 0),
+            (Field::BlockElements, 3),
+        ]
+    );
+}
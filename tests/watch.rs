@@ -0,0 +1,66 @@
+//! ファイルの変更を監視して再ビルドすること
+
+mod common;
+
+use common::{config, project_dir};
+use std::fs;
+use std::sync::mpsc;
+use std::time::Duration;
+
+// ビルドするたびに、各ファイルのエラーの code を送る
+fn spawn_watch(config: bokuteki::BuildConfig) -> mpsc::Receiver<Vec<Vec<&'static str>>> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        bokuteki::watch::watch(&config, |result| {
+            let report = result.unwrap();
+            let codes = report
+                .files
+                .iter()
+                .map(|file| file.errors.iter().map(|error| error.code).collect())
+                .collect();
+            let _ = sender.send(codes);
+        })
+    });
+    receiver
+}
+
+#[test]
+fn new_import_targets_are_noticed_without_spurious_rebuilds() {
+    let dir = project_dir("watch-new-file");
+    fs::write(dir.join("main.bok"), "import 'later';\n\n# Main\n").unwrap();
+    let builds = spawn_watch(config(&dir));
+    assert_eq!(builds.recv().unwrap(), vec![vec!["missing-import"]]);
+
+    // 出力ディレクトリを作ったことでは再ビルドしない
+    assert!(builds.recv_timeout(Duration::from_millis(1000)).is_err());
+
+    fs::write(dir.join("later.bok"), "# Later\n").unwrap();
+    let codes = builds.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(codes, vec![Vec::<&str>::new(), vec![]]);
+    assert!(dir.join("output/later.html").is_file());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn changes_are_rebuilt() {
+    let dir = project_dir("watch-change");
+    fs::write(dir.join("main.bok"), "# Main\n").unwrap();
+    let builds = spawn_watch(config(&dir));
+    assert_eq!(builds.recv().unwrap(), vec![Vec::<&str>::new()]);
+
+    // 更新時刻の分解能が粗いファイルシステムでも変更が分かるように待つ
+    std::thread::sleep(Duration::from_millis(1100));
+    fs::write(dir.join("main.bok"), "# Main\n\n```\n").unwrap();
+    let codes = builds.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(codes, vec![vec!["unterminated-block"]]);
+
+    std::thread::sleep(Duration::from_millis(1100));
+    fs::write(dir.join("main.bok"), "# Changed\n").unwrap();
+    let codes = builds.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(codes, vec![Vec::<&str>::new()]);
+    let html = fs::read_to_string(dir.join("output/main.html")).unwrap();
+    assert!(html.contains("Changed"));
+
+    let _ = fs::remove_dir_all(&dir);
+}